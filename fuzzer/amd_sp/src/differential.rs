@@ -0,0 +1,103 @@
+/// Differential runs of a corpus against several Zen generations
+/// Each generation replays the translated corpus in its own process (one QEMU per process),
+/// inputs whose verdicts differ between the generations are written to the report
+
+use libasp::*;
+
+use chrono::Local;
+use std::env;
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf,
+};
+use std::process::{
+    exit,
+    Command,
+};
+
+pub fn differential(yaml_path: &str, against: &[String], corpus: &[String], output: Option<&str>, run_dir_name: Option<&str>) {
+    let mut yaml_paths = vec![yaml_path.to_string()];
+    yaml_paths.extend(against.iter().cloned());
+    for path in yaml_paths.iter() {
+        if !Path::new(path).exists() {
+            println!("YAML file path does not exist: {}", path);
+            exit(2);
+        }
+    }
+    let confs: Vec<YAMLConfig> = yaml_paths.iter().map(|path| YAMLConfig::new(path)).collect();
+    for conf in confs.iter() {
+        if !Path::new(&conf.flash_base).exists() {
+            println!("UEFI file path does not exist: {}", &conf.flash_base);
+            exit(5);
+        }
+    }
+
+    // Generations label the runs, the same generation might be compared twice
+    let mut labels: Vec<String> = vec![];
+    for (i, conf) in confs.iter().enumerate() {
+        let mut label = conf.qemu_zen.clone();
+        if labels.contains(&label) {
+            label = format!("{}_{}", label, i);
+        }
+        labels.push(label);
+    }
+
+    let inputs = corpus_files(&corpus.iter().map(PathBuf::from).collect::<Vec<PathBuf>>());
+    if inputs.is_empty() {
+        println!("No inputs found in {:?}", corpus);
+        exit(10);
+    }
+
+    let run_dir_name = match run_dir_name {
+        Some(run_dir_name) => run_dir_name.to_string(),
+        None => format!("{}", Local::now().format("%Y-%m-%d_%H:%M")),
+    };
+    let run_dir = PathBuf::from(format!("runs/{}", run_dir_name));
+    if run_dir.exists() {
+        fs::remove_dir_all(&run_dir).unwrap();
+    }
+    fs::create_dir_all(&run_dir).unwrap();
+
+    // Base images are read and parsed once per config
+    let bases: Vec<FlashBase> = confs.iter().map(FlashBase::new).collect();
+
+    // Translate the corpus and replay it for each generation
+    let mut names = vec![];
+    let mut verdicts = vec![];
+    for (i, (conf, label)) in confs.iter().zip(labels.iter()).enumerate() {
+        let input_dir = run_dir.join(format!("inputs_{}", label));
+        fs::create_dir_all(&input_dir).unwrap();
+        for input in inputs.iter() {
+            let name = input.file_name().unwrap().to_string_lossy().to_string();
+            let bytes = fs::read(input).unwrap();
+            let bytes = if i == 0 { bytes } else { translate_input(&confs[0], &bases[0], conf, &bases[i], &bytes) };
+            fs::write(input_dir.join(&name), bytes).unwrap();
+            if i == 0 {
+                names.push(name);
+            }
+        }
+        let verdicts_path = run_dir.join(format!("verdicts_{}.log", label));
+        println!("Replaying {} inputs on {}", inputs.len(), label);
+        let status = Command::new(env::current_exe().unwrap())
+            .arg("-y").arg(&yaml_paths[i])
+            .arg("-r").arg(format!("{}/{}", run_dir_name, label))
+            .arg("replay")
+            .arg("-c").arg(&input_dir)
+            .arg("-o").arg(&verdicts_path)
+            .status()
+            .expect("Unable to start the replay");
+        if !status.success() {
+            println!("Replay on {} failed with {}", label, status);
+        }
+        verdicts.push(read_verdicts(&verdicts_path));
+    }
+
+    let (report, num_diffs) = diff_report(&labels, &names, &verdicts);
+    let output = match output {
+        Some(output) => PathBuf::from(output),
+        None => run_dir.join("diff.yaml"),
+    };
+    fs::write(&output, report).unwrap();
+    println!("{} of {} inputs differ between {}, written to {:?}", num_diffs, names.len(), labels.join(", "), output);
+}
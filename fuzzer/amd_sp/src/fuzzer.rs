@@ -2,11 +2,20 @@ use libafl::corpus::ondisk::OnDiskMetadataFormat;
 use libafl::prelude::*;
 use libafl_qemu::*;
 use libafl_qemu::drcov::QemuDrCovHelper;
+use libafl_qemu::cmplog::{
+    CmpLogObserver,
+    CMPLOG_MAP,
+};
 
 use libasp::*;
 
-use rangemap::RangeMap;
-use clap::Parser;
+use crate::differential::differential;
+use crate::gen_config::gen_config;
+
+use clap::{
+    Parser,
+    Subcommand,
+};
 
 use log;
 use chrono::Local;
@@ -18,6 +27,7 @@ use std::path::{
 };
 use std::time::Duration;
 use std::cell::RefCell;
+use std::collections::HashSet;
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::process::exit;
@@ -40,8 +50,24 @@ static mut COUNTER_EDGE_HOOKS: usize = 0;
 static mut COUNTER_WRITE_HOOKS: usize = 0;
 static mut COUNTER_SNAPSHOT: usize = 0;
 static mut CRASH_SNAPSHOT: bool = false;
-static mut FLASH_READ_HOOK_ID: usize = 0;
+static mut COUNTER_READ_HOOKS: usize = 0;
+/* Read/write hook ids of accesses which can't hit a watchpoint, only generated for the sanitizer */
+static mut WATCH_SKIPPED_READ_IDS: Option<HashSet<u64>> = None;
+static mut WATCH_SKIPPED_WRITE_IDS: Option<HashSet<u64>> = None;
 static mut RUN_DIR_NAME: Option<String> = None;
+/* Tunnel discovery instead of fuzzing: (corpus paths, output path) */
+static mut TUNNEL_DISCOVERY: Option<(Vec<String>, Option<String>)> = None;
+/* Replay of a corpus writing the verdict of each input: (corpus paths, output path) */
+static mut REPLAY: Option<(Vec<String>, String)> = None;
+/* Coverage report of a corpus instead of fuzzing: (corpus paths, output directory) */
+static mut COVERAGE_REPORT: Option<(Vec<String>, Option<String>)> = None;
+/* Resume the campaign of the run directory instead of starting over */
+static mut RESUME: bool = false;
+/* Wall-clock time in between two checkpoints of the state */
+const CHECKPOINT_PERIOD: Duration = Duration::from_secs(60);
+/* Block hook id only used for the hang detection (block budget, loops) */
+const HANG_HOOK_ID: u64 = 0;
+
 #[cfg(feature = "multicore")]
 static mut NUM_CORES: Option<u32> = None;
 
@@ -55,62 +81,47 @@ where
     QT: QemuHelperTuple<S>,
 {
     let conf = borrow_global_conf().unwrap();
-    for no_exec in conf.crashes_mmap_no_exec.iter() {
-        if src >= no_exec[0] && src < no_exec[1] {
-            log::debug!("Generate block:");
-            log::debug!("> src: {:#x}", src);
-            unsafe { COUNTER_EDGE_HOOKS += 1 };
-            log::debug!("> id: {:#x}", unsafe { COUNTER_EDGE_HOOKS });
-            let emu = unsafe { (EMULATOR as *const Emulator).as_ref().unwrap() };
-            emu.current_cpu().unwrap().trigger_breakpoint();
-            return Some(unsafe { COUNTER_EDGE_HOOKS } as u64);
-        }
-    }
-    if conf.crashes_mmap_no_write_flash_fn.len() != 0 {
-        if conf.crashes_mmap_flash_read_fn == src {
-            log::debug!("Adding block hook for flash_read_fn");
-            unsafe {
-                COUNTER_EDGE_HOOKS += 1;
-                FLASH_READ_HOOK_ID = COUNTER_EDGE_HOOKS;
-            }
-            return Some(unsafe { COUNTER_EDGE_HOOKS } as u64);
-        }
+    if watch_gen_block(src) || coverage_enabled() {
+        log::debug!("Generate block:");
+        log::debug!("> src: {:#x}", src);
+        unsafe { COUNTER_EDGE_HOOKS += 1 };
+        log::debug!("> id: {:#x}", unsafe { COUNTER_EDGE_HOOKS });
+        coverage_gen_block(unsafe { COUNTER_EDGE_HOOKS } as u64, src);
+        return Some(unsafe { COUNTER_EDGE_HOOKS } as u64);
+    }
+    if conf.harness_block_budget != 0 || conf.harness_loop_threshold != 0 {
+        return Some(HANG_HOOK_ID);
     }
     None
 }
 
 extern "C" fn exec_block_hook(id: u64, data: u64) {
     let emu = unsafe { (EMULATOR as *const Emulator).as_ref().unwrap() };
-    if unsafe { FLASH_READ_HOOK_ID } == id as usize {
-        let conf = borrow_global_conf().unwrap();
-        let cpu = emu.current_cpu().unwrap();
-        let pc: u64 = cpu.read_reg(Regs::Pc).unwrap();
-        log::debug!("Flash read fn id was hit");
-        if pc as GuestAddr == conf.crashes_mmap_flash_read_fn {
-            let cpy_src: GuestAddr = cpu.read_reg::<libafl_qemu::Regs, u64>(Regs::R0).unwrap() as GuestAddr;
-            let cpy_dest_start: GuestAddr = cpu.read_reg::<libafl_qemu::Regs, u64>(Regs::R1).unwrap() as GuestAddr;
-            let cpy_len: GuestAddr = cpu.read_reg::<libafl_qemu::Regs, u64>(Regs::R2).unwrap() as GuestAddr;
-            let cpy_dest_end: GuestAddr = cpy_dest_start + cpy_len;
-            log::debug!("Flash read fn from {:#010x} to {:#010x} for {:#x} bytes", cpy_src, cpy_dest_start, cpy_len);
-            for area in &conf.crashes_mmap_no_write_flash_fn {
-                if (area.0 >= cpy_dest_start && area.0 < cpy_dest_end) ||
-                    (area.1 >= cpy_dest_start && area.1 < cpy_dest_end) {
-                    log::debug!("Flash read fn writes to [{:#010x}, {:#010x}]", area.0, area.1);
-                    let cpy_lr: GuestAddr = cpu.read_reg::<libafl_qemu::Regs, u64>(Regs::Lr).unwrap() as GuestAddr;
-                    log::debug!("Flash read fn called from {:#010x}", cpy_lr);
-                    if !area.2.contains(&cpy_lr) {
-                        log::info!("Flash read fn hook triggered!");
-                        cpu.trigger_breakpoint();
-                    }
-                }
-            }
-        }
-    } else {
-        log::debug!("Execute block:");
-        log::debug!("> id: {}", id);
-        log::debug!("> data: {}", data);
+    let conf = borrow_global_conf().unwrap();
+    let budget = conf.harness_block_budget;
+    if budget != 0 && hang_count_block() > budget {
+        log::debug!("Block budget of {} exhausted", budget);
+        hang_detected(HangReason::BlockBudget);
+        emu.current_cpu().unwrap().trigger_breakpoint();
+        return;
+    }
+    if conf.harness_loop_threshold != 0 && hang_check_loop(emu, conf.harness_loop_threshold) {
+        log::debug!("Loop detected");
+        hang_detected(HangReason::Loop);
         emu.current_cpu().unwrap().trigger_breakpoint();
+        return;
+    }
+    if id == HANG_HOOK_ID {
+        return;
+    }
+    if coverage_enabled() {
+        coverage_exec_block(id);
     }
+    log::debug!("Execute block:");
+    log::debug!("> id: {}", id);
+    log::debug!("> data: {}", data);
+    let pc: u64 = emu.current_cpu().unwrap().read_reg(Regs::Pc).unwrap();
+    watch_exec(pc as GuestAddr);
 }
 
 fn gen_writes_hook<QT, S>(
@@ -124,48 +135,110 @@ where
     QT: QemuHelperTuple<S>,
 {
     let conf = borrow_global_conf().unwrap();
-    for no_write in conf.crashes_mmap_no_write_hooks.iter() {
-        for no_ldr in &no_write.2 {
-            if src == *no_ldr {
-                log::debug!("Skipping generation hook for {:#010x}", src);
-                return None;
-            }
-        }
+    let skip = !watch_reaches(WatchAccess::Write, src);
+    // The sanitizer needs all stores, the watchpoints are skipped per id
+    if skip && !conf.sanitizer_enabled {
+        log::debug!("Skipping generation hook for {:#010x}", src);
+        return None;
     }
     log::debug!("Generate writes:");
     log::debug!("> src: {:#x}", src);
     log::debug!("> size: {}", size);
     unsafe { COUNTER_WRITE_HOOKS += 1 };
     log::debug!("> id: {:#x}", unsafe { COUNTER_WRITE_HOOKS });
-    return Some(unsafe { COUNTER_WRITE_HOOKS } as u64);
+    let id = unsafe { COUNTER_WRITE_HOOKS } as u64;
+    if skip {
+        unsafe { WATCH_SKIPPED_WRITE_IDS.as_mut().unwrap().insert(id); }
+    } else {
+        watch_gen_store(id, src);
+    }
+    if conf.sanitizer_enabled {
+        sanitizer_gen_store(id, src);
+    }
+    return Some(id);
 }
 
-extern "C" fn exec_writes_hook(id: u64, addr: GuestAddr, data: u64) {
+extern "C" fn exec_writes_hook_1(id: u64, addr: GuestAddr, data: u64) {
+    exec_writes_hook_n(id, addr, 1, data);
+}
+extern "C" fn exec_writes_hook_2(id: u64, addr: GuestAddr, data: u64) {
+    exec_writes_hook_n(id, addr, 2, data);
+}
+extern "C" fn exec_writes_hook_4(id: u64, addr: GuestAddr, data: u64) {
+    exec_writes_hook_n(id, addr, 4, data);
+}
+extern "C" fn exec_writes_hook_8(id: u64, addr: GuestAddr, data: u64) {
+    exec_writes_hook_n(id, addr, 8, data);
+}
+extern "C" fn exec_writes_hook_n(id: u64, addr: GuestAddr, size: usize, data: u64) {
     let conf = borrow_global_conf().unwrap();
-    for no_write in conf.crashes_mmap_no_write_hooks.iter() {
-        if addr >= no_write.0 && addr < no_write.1 {
-            log::debug!("Execute writes:");
-            log::debug!("> id: {:#x}", id);
-            log::debug!("> addr: {:#x}", addr);
-            log::debug!("> data: {}", data);
-            let emu = unsafe { (EMULATOR as *const Emulator).as_ref().unwrap() };
-            emu.current_cpu().unwrap().trigger_breakpoint();
-        }
+    if conf.sanitizer_enabled {
+        sanitizer_store(id, addr, size);
+    }
+    if unsafe { WATCH_SKIPPED_WRITE_IDS.as_ref().unwrap().contains(&id) } {
+        return;
     }
+    log::debug!("Execute writes:");
+    log::debug!("> id: {:#x}", id);
+    log::debug!("> addr: {:#x}", addr);
+    log::debug!("> size: {}", size);
+    log::debug!("> data: {}", data);
+    watch_store(id, addr, size);
 }
-extern "C" fn exec_writes_hook_n(id: u64, addr: GuestAddr, size: usize, data: u64) {
+
+fn gen_reads_hook<QT, S>(
+    _hooks: &mut QemuHooks<QT, S>,
+    _id: Option<&mut S>,
+    src: GuestAddr,
+    size: usize,
+) -> Option<u64>
+where
+    S: UsesInput,
+    QT: QemuHelperTuple<S>,
+{
     let conf = borrow_global_conf().unwrap();
-    for no_write in conf.crashes_mmap_no_write_hooks.iter() {
-        if addr >= no_write.0 && addr < no_write.1 {
-            log::debug!("Execute writes:");
-            log::debug!("> id: {:#x}", id);
-            log::debug!("> addr: {:#x}", addr);
-            log::debug!("> size: {}", size);
-            log::debug!("> data: {}", data);
-            let emu = unsafe { (EMULATOR as *const Emulator).as_ref().unwrap() };
-            emu.current_cpu().unwrap().trigger_breakpoint();
-        }
+    let skip = !watch_reaches(WatchAccess::Read, src);
+    // The sanitizer needs all loads, the watchpoints are skipped per id
+    if skip && !conf.sanitizer_enabled {
+        return None;
+    }
+    log::debug!("Generate reads:");
+    log::debug!("> src: {:#x}", src);
+    log::debug!("> size: {}", size);
+    unsafe { COUNTER_READ_HOOKS += 1 };
+    let id = unsafe { COUNTER_READ_HOOKS } as u64;
+    if skip {
+        unsafe { WATCH_SKIPPED_READ_IDS.as_mut().unwrap().insert(id); }
+    } else {
+        watch_gen_load(id, src);
+    }
+    if conf.sanitizer_enabled {
+        sanitizer_gen_load(id, src);
+    }
+    return Some(id);
+}
+
+extern "C" fn exec_reads_hook_1(id: u64, addr: GuestAddr, data: u64) {
+    exec_reads_hook_n(id, addr, 1, data);
+}
+extern "C" fn exec_reads_hook_2(id: u64, addr: GuestAddr, data: u64) {
+    exec_reads_hook_n(id, addr, 2, data);
+}
+extern "C" fn exec_reads_hook_4(id: u64, addr: GuestAddr, data: u64) {
+    exec_reads_hook_n(id, addr, 4, data);
+}
+extern "C" fn exec_reads_hook_8(id: u64, addr: GuestAddr, data: u64) {
+    exec_reads_hook_n(id, addr, 8, data);
+}
+extern "C" fn exec_reads_hook_n(id: u64, addr: GuestAddr, size: usize, _data: u64) {
+    let conf = borrow_global_conf().unwrap();
+    if conf.sanitizer_enabled {
+        sanitizer_load(id, addr, size);
+    }
+    if unsafe { WATCH_SKIPPED_READ_IDS.as_ref().unwrap().contains(&id) } {
+        return;
     }
+    watch_load(id, addr, size);
 }
 
 extern "C" {
@@ -220,6 +293,12 @@ fn print_input(input: &[u8]) {
     }
     out_str.push_str(&format!("\n]"));
 
+    // Decoded input fields
+    let conf = borrow_global_conf().unwrap();
+    for field in conf.input_layout.decode(input) {
+        out_str.push_str(&format!("\n{}", field));
+    }
+
     log::info!("{}", out_str);
 }
 
@@ -234,7 +313,7 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
     } else {
         PathBuf::from(format!("runs/{}", date.format("%Y-%m-%d_%H:%M")))
     };
-    if !env::var("AFL_LAUNCHER_CLIENT".to_string()).is_ok() {
+    if !env::var("AFL_LAUNCHER_CLIENT".to_string()).is_ok() && unsafe { !RESUME } {
         if run_dir.exists() {
             fs::remove_dir_all(&run_dir).unwrap();
         }
@@ -249,10 +328,34 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
     let mut solutions_dir = run_dir.clone();
     solutions_dir.push("solutions");
     fs::create_dir_all(&solutions_dir).unwrap();
+    let mut hangs_dir = run_dir.clone();
+    hangs_dir.push("hangs");
+    fs::create_dir_all(&hangs_dir).unwrap();
+    let mut smashes_dir = run_dir.clone();
+    smashes_dir.push("smashes");
+    fs::create_dir_all(&smashes_dir).unwrap();
+    let mut queue_dir = run_dir.clone();
+    queue_dir.push("queue");
+    fs::create_dir_all(&queue_dir).unwrap();
+    let mut state_dir = run_dir.clone();
+    state_dir.push("state");
+    fs::create_dir_all(&state_dir).unwrap();
     let mut config_path = run_dir.clone();
     config_path.push("config.yaml");
     if !env::var("AFL_LAUNCHER_CLIENT".to_string()).is_ok() {
-        fs::copy(&conf.config_file,&config_path).unwrap();
+        if unsafe { !RESUME } {
+            fs::copy(&conf.config_file,&config_path).unwrap();
+        } else {
+            if fs::read(&conf.config_file).ok() != fs::read(&config_path).ok() {
+                log::warn!("Resuming with {} which differs from {:?}", &conf.config_file, config_path);
+            }
+            // Resumed with fewer cores, the queues of the missing clients go to the first one
+            #[cfg(feature = "multicore")]
+            let num_clients = unsafe { *NUM_CORES.as_ref().unwrap() } as usize;
+            #[cfg(not(feature = "multicore"))]
+            let num_clients = 1;
+            resume_clients(&queue_dir, &state_dir, num_clients);
+        }
     }
 
     // Generate initial inputs
@@ -262,6 +365,7 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
         conf.flash_size as GuestAddr,
         conf.input_total_size,
         input_dir,
+        &conf.flash_base,
     );
 
     // Catching stdout/stderr
@@ -287,6 +391,15 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
         dup2(run_fd, io::stderr().as_raw_fd()).unwrap();
     }
 
+    // Configure input fixups
+    let input_fixups = InputFixups::new(
+        &conf.input_fixups,
+        &conf.input_mem,
+        &conf.input_fixed,
+        conf.flash_size,
+        &conf.flash_base,
+    );
+
     // Configure ResetState and ExceptionHandler helpers
     let mut rs = ResetState::new(conf.qemu_sram_size);
     let eh = ExceptionHandler::new(ON_CHIP_ADDR);
@@ -309,8 +422,54 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
     // Catching exceptions
     eh.start(&emu);
     // Setup tunnels cmps
-    for cmp in &conf.tunnels_cmps {
-        add_tunnels_cmp((*cmp).0, &(*cmp).1, &emu);
+    for tunnel in &conf.tunnels_cmps {
+        add_tunnels_cmp(tunnel, &emu);
+    }
+    // Setup WFI/WFE hooks
+    if conf.harness_wait_masked {
+        let on_chip_bl = fs::read(&conf.qemu_on_chip_bl_path).unwrap();
+        add_wait_masked_hooks(&on_chip_bl, ON_CHIP_ADDR, &emu);
+    }
+    // Setup function stubs
+    for stub in &conf.stubs {
+        add_stub(stub, &emu);
+    }
+    // Setup CCP fast-path
+    add_ccp_fast_path(conf.ccp_mode, &conf.ccp_verify, &emu);
+    // Setup copy functions
+    for copy_fn in &conf.crashes_copy_fns {
+        add_copy_fn(copy_fn, &emu);
+    }
+    // Setup device write checks
+    if !conf.crashes_dma.is_empty() {
+        dma_init(&emu);
+    }
+    // Setup watchpoints
+    watch_init(&emu);
+    unsafe {
+        WATCH_SKIPPED_READ_IDS = Some(HashSet::new());
+        WATCH_SKIPPED_WRITE_IDS = Some(HashSet::new());
+    }
+    // Setup sanitizer
+    if conf.sanitizer_enabled {
+        sanitizer_init(&emu);
+    }
+    // Setup shadow call stack
+    if conf.crashes_shadow_stack {
+        let on_chip_bl = fs::read(&conf.qemu_on_chip_bl_path).unwrap();
+        add_shadow_stack_hooks(&on_chip_bl, ON_CHIP_ADDR, &emu);
+    }
+    // Setup CmpLog hooks or tunnel discovery hooks
+    if unsafe { TUNNEL_DISCOVERY.is_some() } {
+        let on_chip_bl = fs::read(&conf.qemu_on_chip_bl_path).unwrap();
+        add_discovery_hooks(&on_chip_bl, ON_CHIP_ADDR, &emu);
+    } else if conf.cmplog_enabled {
+        let on_chip_bl = fs::read(&conf.qemu_on_chip_bl_path).unwrap();
+        add_cmplog_hooks(&on_chip_bl, ON_CHIP_ADDR, &emu);
+    }
+    // Record the coverage of all blocks for the coverage report
+    if unsafe { COVERAGE_REPORT.is_some() } {
+        coverage_enable();
     }
     // Setup crash breakpoints
     for bp in &conf.crashes_breakpoints {
@@ -344,6 +503,7 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
         let mut buffer = vec![0; conf.input_total_size];
         buffer[..target_buf.len()].copy_from_slice(target_buf);
         let mut buffer = buffer.as_slice();
+        let input_buffer = buffer;
         cpu = emu.current_cpu().unwrap(); // ctx switch safe
         for mem in conf.input_mem.iter() {
             unsafe { write_flash_mem(mem.0, &buffer[..mem.1]); }
@@ -356,16 +516,90 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
             unsafe { write_flash_mem(fixed.0, &buffer); }
         }
 
+        // Fixups (e.g. checksums) to memory
+        if !input_fixups.is_empty() {
+            for fixup in input_fixups.apply(input_buffer) {
+                unsafe { write_flash_mem(fixup.0, &fixup.1); }
+            }
+        }
+
         // Start the emulation
         let mut pc: u64 = cpu.read_reg(Regs::Pc).unwrap();
         log::debug!("Start at {:#x}", pc);
+        hang_reset();
+        if conf.sanitizer_enabled {
+            sanitizer_reset();
+        }
+        shadow_stack_reset();
+        watch_reset();
+        copy_fns_reset();
+        dma_reset();
+        coverage_reset();
         emu.start(&cpu);
+        // Jumping tunnels and stubs stop the emulation to continue at the new PC
+        while tunnels_jumped() {
+            emu.start(&cpu);
+        }
 
         // After the emulator finished
         pc = cpu.read_reg(Regs::Pc).unwrap();
         let r0: u64 = cpu.read_reg(Regs::R0).unwrap();
         log::debug!("End at {:#x} with R0={:#x}", pc, r0);
         unsafe { COUNTER_SNAPSHOT += 1; }
+        // Hangs detected during the execution
+        if let Some(reason) = hang_reason() {
+            unsafe {
+                COUNTER_SNAPSHOT = 0;
+                CRASH_SNAPSHOT = true;
+            }
+            log::info!("Found hang ({:?}) at {:#x}", reason, pc);
+            return ExitKind::Timeout;
+        }
+        // Sanitizer violations during the execution
+        if let Some(violation) = sanitizer_violation() {
+            unsafe {
+                COUNTER_SNAPSHOT = 0;
+                CRASH_SNAPSHOT = true;
+            }
+            log::info!("Found sanitizer violation: {:?}", violation);
+            return ExitKind::Crash;
+        }
+        // Watchpoint violations during the execution
+        if let Some(violation) = watch_violation() {
+            unsafe {
+                COUNTER_SNAPSHOT = 0;
+                CRASH_SNAPSHOT = true;
+            }
+            log::info!("Found watchpoint violation: {:?}", violation);
+            return ExitKind::Crash;
+        }
+        // Copies into protected regions during the execution
+        if let Some(violation) = copy_fn_violation() {
+            unsafe {
+                COUNTER_SNAPSHOT = 0;
+                CRASH_SNAPSHOT = true;
+            }
+            log::info!("Found copy into protected region: {:?}", violation);
+            return ExitKind::Crash;
+        }
+        // Device writes into protected regions during the execution
+        if let Some(violation) = dma_violation() {
+            unsafe {
+                COUNTER_SNAPSHOT = 0;
+                CRASH_SNAPSHOT = true;
+            }
+            log::info!("Found device write into protected region: {:?}", violation);
+            return ExitKind::Crash;
+        }
+        // Smashed return addresses are saved to their own corpus
+        if let Some(violation) = shadow_stack_violation() {
+            unsafe {
+                COUNTER_SNAPSHOT = 0;
+                CRASH_SNAPSHOT = true;
+            }
+            log::info!("Found smashed return address: {:?}", violation);
+            return ExitKind::Ok;
+        }
         // Look for crashes if no sink was hit
         if !conf.harness_sinks.iter().any(|&v| v == pc as GuestAddr) {
             // Don't trigger on exceptions
@@ -391,8 +625,15 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
         let edges_observer =
             HitcountsMapObserver::new(VariableMapObserver::new("edges", edges, edges_counter));
 
+        // Create an observation channel for the comparison operands
+        let cmplog_observer = CmpLogObserver::new("cmplog", unsafe { &mut CMPLOG_MAP }, true);
+
         // Feedback to rate the interestingness of an input
-        let mut feedback = MaxMapFeedback::new(&edges_observer);
+        // The minimizer of the scheduler needs the indexes of the coverage map of each entry
+        let mut feedback = MaxMapFeedback::new_tracking(&edges_observer, conf.scheduler.tracks_indexes(), false);
+
+        // Calibration of the execution time and coverage of new entries for power schedules
+        let calibration = CalibrationStage::new(&feedback);
 
         #[allow(unused_assignments, unused_mut)]
         let mut objective_coverage_feedback =
@@ -405,22 +646,33 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
         }
 
         // A feedback to choose if an input is a solution or not
-        let mut objective = feedback_and_fast!(
+        // Hangs and smashed return addresses are saved to their own corpus and never are solutions
+        let mut objective = feedback_or!(
             feedback_and_fast!(
-                feedback_or!(CrashFeedback::new(), ExceptionFeedback::new()),
-                objective_coverage_feedback
+                feedback_and_fast!(
+                    feedback_or!(CrashFeedback::new(), ExceptionFeedback::new()),
+                    objective_coverage_feedback
+                ),
+                CustomMetadataFeedback::new( unsafe { EMULATOR } ) // always true, used to write metadata output whenever a test-case is a solution
             ),
-            CustomMetadataFeedback::new( unsafe { EMULATOR } ) // always true, used to write metadata output whenever a test-case is a solution
+            HangFeedback::new( unsafe { EMULATOR }, hangs_dir.clone() ),
+            ShadowStackFeedback::new( smashes_dir.clone() )
         );
 
-        // create a State from scratch
+        // Restore the state of the last checkpoint or create a State from scratch
+        let checkpoint_path = checkpoint_path(&state_dir);
         let mut cloned_solutions_dir = solutions_dir.clone();
-        let mut state = state.unwrap_or_else(|| {
+        let client_queue_dir = client_queue_dir(&queue_dir);
+        let cloned_queue_dir = client_queue_dir.clone();
+        let mut state = state.or_else(|| {
+            if unsafe { RESUME } { load_checkpoint(&checkpoint_path) } else { None }
+        }).unwrap_or_else(|| {
             StdState::new(
                 // RNG
                 StdRand::with_seed(current_nanos()),
-                // Corpus that will be evolved, we keep it in memory for performance
-                InMemoryCorpus::new(),
+                // Corpus that will be evolved, on disk to resume the campaign
+                // with the most recently used entries cached for performance
+                CachedOnDiskCorpus::new(cloned_queue_dir, 4096).unwrap(),
                 // Corpus in which we store solutions,
                 // on disk so the user can get them after stopping the fuzzer
                 OnDiskCorpus::new_save_meta(cloned_solutions_dir, Some(OnDiskMetadataFormat::JsonPretty)).unwrap(),
@@ -436,8 +688,34 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
         // Maximum input length
         state.set_max_size(conf.input_total_size);
 
-        // A queue policy to get testcasess from the corpus
-        let scheduler = QueueScheduler::new();
+        // Token dictionary from the on-chip bootloader comparisons
+        if state.metadata().get::<Tokens>().is_none() {
+            let mut values = std::collections::BTreeSet::new();
+            if conf.tokens_on_chip_bl {
+                let on_chip_bl = fs::read(&conf.qemu_on_chip_bl_path).unwrap();
+                values.extend(harvest_cmp_values(&on_chip_bl));
+            }
+            values.extend(conf.tokens_extra.iter());
+            if !values.is_empty() {
+                log::info!("Adding {} tokens", values.len());
+                state.add_metadata(values_to_tokens(&values));
+            }
+        }
+
+        // A policy to get testcasess from the corpus, selected by 'scheduler:' of the config
+        log::info!("Scheduler: {:?}", conf.scheduler);
+        let scheduler: StdCampaignScheduler<_, _> = match conf.scheduler {
+            SchedulerKind::Queue => CampaignScheduler::Queue(QueueScheduler::new()),
+            SchedulerKind::Minimizer => CampaignScheduler::Minimizer(
+                IndexesLenTimeMinimizerScheduler::new(QueueScheduler::new())
+            ),
+            SchedulerKind::Power(schedule) => CampaignScheduler::Power(
+                IndexesLenTimeMinimizerScheduler::new(PowerQueueScheduler::new(&mut state, &edges_observer, schedule))
+            ),
+            SchedulerKind::Weighted(schedule) => CampaignScheduler::Weighted(
+                IndexesLenTimeMinimizerScheduler::new(StdWeightedScheduler::with_schedule(&mut state, &edges_observer, Some(schedule)))
+            ),
+        };
 
         // A fuzzer with feedbacks and a corpus scheduler
         let mut fuzzer = StdFuzzer::new(scheduler, feedback, objective);
@@ -445,17 +723,13 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
         // Configure DrCov helper
         let mut log_drcov_path = log_dir.clone();
         log_drcov_path.push("drcov.log");
-        let mut rangemap = RangeMap::<usize, (u16, String)>::new();
-        rangemap.insert(
-            (0x0 as usize)..(0xffff_9000 as usize),
-            (0, "on-chip-ryzen-zen.bl".to_string()),
-        );
+        let rangemap = drcov_modules(conf, ON_CHIP_ADDR);
 
         // Configure QEMU hook helper
         let mut hooks = QemuHooks::new(&emu, tuple_list!(
-                QemuEdgeCoverageHelper::new(QemuInstrumentationFilter::None),
+                QemuEdgeCoverageHelper::new(coverage_filter(conf)),
                 QemuDrCovHelper::new(
-                    QemuInstrumentationFilter::None,
+                    coverage_filter(conf),
                     rangemap,
                     log_drcov_path,
                     false,
@@ -464,22 +738,35 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
 
         // Block hooks and write hooks for crash detection
         hooks.blocks_raw(Some(gen_block_hook), Some(exec_block_hook));
-        if conf.crashes_mmap_no_write_hooks.len() != 0 {
+        if is_watched(WatchAccess::Write) || conf.sanitizer_enabled {
             log::debug!("Adding write generation hooks");
             hooks.writes_raw(
                 Some(gen_writes_hook),
-                Some(exec_writes_hook),
-                Some(exec_writes_hook),
-                Some(exec_writes_hook),
-                Some(exec_writes_hook),
+                Some(exec_writes_hook_1),
+                Some(exec_writes_hook_2),
+                Some(exec_writes_hook_4),
+                Some(exec_writes_hook_8),
                 Some(exec_writes_hook_n)
             );
         } else {
             log::debug!("No write generation hooks");
         }
+        // Read hooks for watchpoints and the sanitizer of uninitialized buffers
+        if is_watched(WatchAccess::Read) ||
+            (conf.sanitizer_enabled && conf.sanitizer_buffers.iter().any(|buffer| buffer.uninit)) {
+            log::debug!("Adding read generation hooks");
+            hooks.reads_raw(
+                Some(gen_reads_hook),
+                Some(exec_reads_hook_1),
+                Some(exec_reads_hook_2),
+                Some(exec_reads_hook_4),
+                Some(exec_reads_hook_8),
+                Some(exec_reads_hook_n)
+            );
+        }
 
-        let timeout = Duration::new(5, 0); // 5sec
-        let mut executor = TimeoutExecutor::new(
+        let timeout = Duration::from_millis(conf.harness_timeout);
+        let executor = TimeoutExecutor::new(
             QemuExecutor::new(
                 &mut hooks,
                 &mut harness,
@@ -491,25 +778,167 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
             .unwrap(),
             timeout,
         );
+        // Shadow executor running the CmpLog observer in the tracing stage only
+        let mut executor = ShadowExecutor::new(executor, tuple_list!(cmplog_observer));
+
+        // Tunnel discovery runs the initial inputs and the corpus only
+        if let Some((corpus, output)) = unsafe { TUNNEL_DISCOVERY.as_ref() } {
+            let mut paths = vec![input_dir.clone()];
+            paths.extend(corpus.iter().map(PathBuf::from));
+            let mut num_inputs = 0;
+            for file in corpus_files(&paths).iter() {
+                let input = BytesInput::from_file(file)?;
+                set_discovery_input(input.bytes());
+                fuzzer.execute_input(&mut state, &mut executor, &mut mgr, &input)?;
+                num_inputs += 1;
+            }
+            let report = discovery_report(num_inputs, &conf.tunnels_cmps);
+            let output = match output {
+                Some(output) => PathBuf::from(output),
+                None => {
+                    let mut output = log_dir.clone();
+                    output.push("tunnels.yaml");
+                    output
+                },
+            };
+            fs::write(&output, report).unwrap();
+            log::info!("Tunnel candidates written to {:?}", output);
+            println!("END tunnel discovery");
+            return Ok(());
+        }
 
-        state
-            .load_initial_inputs_forced(&mut fuzzer, &mut executor, &mut mgr, &[input_dir.clone()])
-            .unwrap_or_else(|_| {
-                println!("Failed to load initial corpus at {:?}", &input_dir);
-                std::process::exit(0);
-            });
+        // Coverage report runs the initial inputs and the corpus only
+        if let Some((corpus, output)) = unsafe { COVERAGE_REPORT.as_ref() } {
+            let mut paths = vec![input_dir.clone()];
+            paths.extend(corpus.iter().map(PathBuf::from));
+            let files = corpus_files(&paths);
+            for file in files.iter() {
+                let input = BytesInput::from_file(file)?;
+                fuzzer.execute_input(&mut state, &mut executor, &mut mgr, &input)?;
+            }
+            let on_chip_bl = fs::read(&conf.qemu_on_chip_bl_path).unwrap();
+            let symbols = SymbolMap::load(conf.coverage_symbols.as_ref().unwrap());
+            let functions = coverage_functions(conf, &symbols, &on_chip_bl, ON_CHIP_ADDR);
+            let output = match output {
+                Some(output) => PathBuf::from(output),
+                None => log_dir.clone(),
+            };
+            fs::create_dir_all(&output).unwrap();
+            fs::write(output.join("coverage.json"), coverage_json(&functions, files.len())).unwrap();
+            let module = Path::new(&conf.qemu_on_chip_bl_path).file_name().unwrap().to_string_lossy().to_string();
+            fs::write(output.join("coverage.info"), coverage_lcov(&functions, &module)).unwrap();
+            log::info!("Coverage report of {} inputs written to {:?}", files.len(), output);
+            println!("END coverage report");
+            return Ok(());
+        }
 
-        // Setup a mutational stage with a basic bytes mutator
-        let mutator = StdScheduledMutator::new(havoc_mutations());
-        let mut stages = tuple_list!(StdMutationalStage::new(mutator));
+        // Replay runs the corpus only and writes the verdict of each input
+        if let Some((corpus, output)) = unsafe { REPLAY.as_ref() } {
+            let paths: Vec<PathBuf> = corpus.iter().map(PathBuf::from).collect();
+            let mut verdicts = vec![];
+            for file in corpus_files(&paths).iter() {
+                let input = BytesInput::from_file(file)?;
+                let exit_kind = fuzzer.execute_input(&mut state, &mut executor, &mut mgr, &input)?;
+                let pc: u64 = emu.current_cpu().unwrap().read_reg(Regs::Pc).unwrap();
+                let verdict = match exit_kind {
+                    ExitKind::Crash => DiffVerdict::Crash,
+                    ExitKind::Timeout => DiffVerdict::Hang,
+                    _ => match conf.harness_sinks.iter().position(|&sink| sink == pc as GuestAddr) {
+                        Some(i) => DiffVerdict::Sink(i),
+                        None if shadow_stack_violation().is_some() => DiffVerdict::Smash,
+                        None => DiffVerdict::Exception,
+                    },
+                };
+                log::info!("Replay of {:?}: {:?}", file, verdict);
+                verdicts.push((file.file_name().unwrap().to_string_lossy().to_string(), verdict));
+            }
+            write_verdicts(Path::new(output), &verdicts);
+            println!("END replay");
+            // QEMU keeps running otherwise, the differential run waits for this process
+            exit(0);
+        }
 
+        // Entries of the queue saved after the last checkpoint (or without any checkpoint)
+        // aren't part of the corpus, they are added again after the initial inputs
+        let mut known_names = HashSet::new();
+        for idx in 0..state.corpus().count() {
+            if let Some(filename) = state.corpus().get(idx).unwrap().borrow().filename() {
+                if let Some(name) = Path::new(filename).file_name() {
+                    known_names.insert(name.to_string_lossy().to_string());
+                }
+            }
+        }
+        let orphans = take_orphans(&client_queue_dir, &known_names);
+
+        // A resumed state has its corpus already
+        if state.corpus().count() == 0 {
+            state
+                .load_initial_inputs_forced(&mut fuzzer, &mut executor, &mut mgr, &[input_dir.clone()])
+                .unwrap_or_else(|_| {
+                    println!("Failed to load initial corpus at {:?}", &input_dir);
+                    std::process::exit(0);
+                });
+        } else {
+            log::info!("Resuming with {} entries and {} solutions", state.corpus().count(), state.solutions().count());
+        }
+        for bytes in orphans.into_iter() {
+            fuzzer
+                .add_input(&mut state, &mut executor, &mut mgr, BytesInput::new(bytes))
+                .expect("Failed to add a queue entry");
+        }
+
+        // Setup a tracing stage for the comparison operands and an input-to-state stage
+        let cmplog_enabled = conf.cmplog_enabled;
+        let skip_cmplog = move |_state: &mut _| {
+            if cmplog_enabled { SkippableStageDecision::Perform } else { SkippableStageDecision::Skip }
+        };
+        let tracing = SkippableStage::new(ShadowTracingStage::new(&mut executor), skip_cmplog);
+        let i2s = SkippableStage::new(
+            StdMutationalStage::new(StdScheduledMutator::new(tuple_list!(I2SRandReplace::new()))),
+            skip_cmplog,
+        );
+
+        // Power schedules and weighted selection calibrate new entries and
+        // mutate them as often as their power score allows
+        let calibrated = conf.scheduler.is_calibrated();
+        let skip_calibrated = move |_state: &mut _| {
+            if calibrated { SkippableStageDecision::Perform } else { SkippableStageDecision::Skip }
+        };
+        let skip_uncalibrated = move |_state: &mut _| {
+            if calibrated { SkippableStageDecision::Skip } else { SkippableStageDecision::Perform }
+        };
+        let calibration = SkippableStage::new(calibration, skip_calibrated);
+
+        // Setup a mutational stage with a basic bytes mutator and token mutations
+        // and a mutational stage for the fields of the input layout
+        let mutator = StdScheduledMutator::new(havoc_mutations().merge(tokens_mutations()));
+        let power_mutator = StdScheduledMutator::new(havoc_mutations().merge(tokens_mutations()));
+        let layout_mutator = LayoutMutator::new(conf.input_layout.clone());
+        let mut stages = tuple_list!(
+            calibration,
+            tracing,
+            i2s,
+            SkippableStage::new(StdMutationalStage::new(mutator), skip_uncalibrated),
+            SkippableStage::new(StdPowerMutationalStage::new(power_mutator), skip_calibrated),
+            StdMutationalStage::new(layout_mutator)
+        );
+
+        // The fuzzing loop of LibAFL, the state is checkpointed periodically for --resume
         log::info!("Starting fuzzing loop");
-        fuzzer
-            .fuzz_loop(&mut stages, &mut executor, &mut state, &mut mgr)
-            .expect("Error in the fuzzing loop");
-        log::info!("Ending fuzzing loop");
-        println!("END fuzzing loop");
-        Ok(())
+        let mut last_report = current_time();
+        let mut last_checkpoint = current_time();
+        loop {
+            fuzzer
+                .fuzz_one(&mut stages, &mut executor, &mut state, &mut mgr)
+                .expect("Error in the fuzzing loop");
+            last_report = mgr
+                .maybe_report_progress(&mut state, last_report, STATS_TIMEOUT_DEFAULT)
+                .expect("Error in the fuzzing loop");
+            if current_time() - last_checkpoint >= CHECKPOINT_PERIOD {
+                save_checkpoint(&checkpoint_path, &state);
+                last_checkpoint = current_time();
+            }
+        }
     };
 
     // Logging of LibAFL events
@@ -580,7 +1009,7 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
 struct Args {
    /// YAML config file path
    #[arg(short, long)]
-   yaml_path: String,
+   yaml_path: Option<String>,
 
    /// Run directory name
    #[arg(short, long)]
@@ -589,21 +1018,175 @@ struct Args {
    /// Number of cores
    #[arg(short, long)]
    num_cores: Option<u32>,
+
+   /// Resume the campaign of the run directory (-r) from its queue and state checkpoints
+   #[arg(long)]
+   resume: bool,
+
+   #[command(subcommand)]
+   command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+   /// Generate a starter YAML config from the directory tree of a flash image
+   GenConfig {
+      /// Flash image (UEFI ROM) path
+      #[arg(short, long)]
+      rom: String,
+
+      /// Zen generation (Zen1, Zen+, Zen2, Zen3, ZenTesla)
+      #[arg(short, long)]
+      zen: String,
+
+      /// YAML config to take the non-input sections from (default: yaml/ config of the generation)
+      #[arg(short, long)]
+      template: Option<String>,
+
+      /// Output path (default: stdout)
+      #[arg(short, long)]
+      output: Option<String>,
+   },
+
+   /// Run the initial inputs and a corpus to suggest tunnels for comparisons which are never satisfied
+   Tunnels {
+      /// Corpus directories or inputs to run in addition to the initial inputs
+      #[arg(short, long)]
+      corpus: Vec<String>,
+
+      /// Output path (default: logs/tunnels.yaml of the run directory)
+      #[arg(short, long)]
+      output: Option<String>,
+   },
+
+   /// Run the initial inputs and a corpus and report the block and edge coverage per function
+   Coverage {
+      /// Corpus directories or inputs to run in addition to the initial inputs
+      #[arg(short, long)]
+      corpus: Vec<String>,
+
+      /// Output directory of coverage.json and coverage.info (default: logs/ of the run directory)
+      #[arg(short, long)]
+      output: Option<String>,
+   },
+
+   /// Run a corpus and write the verdict (index of the hit sink, crash, hang) of each input
+   Replay {
+      /// Corpus directories or inputs
+      #[arg(short, long, required = true)]
+      corpus: Vec<String>,
+
+      /// Output path of the verdicts
+      #[arg(short, long)]
+      output: String,
+   },
+
+   /// Compare the scheduler and the last stats of run directories, e.g. of campaigns with different schedulers
+   Compare {
+      /// Run directory names below runs/
+      #[arg(required = true)]
+      runs: Vec<String>,
+
+      /// Output path (default: stdout)
+      #[arg(short, long)]
+      output: Option<String>,
+   },
+
+   /// Run a corpus against other Zen generations and report inputs with different verdicts
+   Diff {
+      /// YAML configs of the other generations
+      #[arg(short, long, required = true)]
+      against: Vec<String>,
+
+      /// Corpus directories or inputs of the YAML config given by -y
+      #[arg(short, long, required = true)]
+      corpus: Vec<String>,
+
+      /// Output path (default: diff.yaml of the run directory)
+      #[arg(short, long)]
+      output: Option<String>,
+   },
 }
 
 fn parse_args() -> Vec<String> {
     let cli_args = Args::parse();
 
+    // Subcommands which don't need to start QEMU
+    match cli_args.command {
+        Some(Command::GenConfig { rom, zen, template, output }) => {
+            gen_config(&rom, &zen, template.as_deref(), output.as_deref());
+            exit(0);
+        },
+        Some(Command::Tunnels { corpus, output }) => {
+            unsafe { TUNNEL_DISCOVERY = Some((corpus, output)); }
+        },
+        Some(Command::Coverage { corpus, output }) => {
+            unsafe { COVERAGE_REPORT = Some((corpus, output)); }
+        },
+        Some(Command::Replay { corpus, output }) => {
+            unsafe { REPLAY = Some((corpus, output)); }
+        },
+        Some(Command::Compare { runs, output }) => {
+            let run_dirs: Vec<PathBuf> = runs.iter().map(|run| PathBuf::from(format!("runs/{}", run))).collect();
+            let report = compare_campaigns(&run_dirs);
+            match output {
+                Some(output) => fs::write(output, report).unwrap(),
+                None => print!("{}", report),
+            }
+            exit(0);
+        },
+        Some(Command::Diff { against, corpus, output }) => {
+            match cli_args.yaml_path.as_deref() {
+                Some(yaml_path) => differential(yaml_path, &against, &corpus, output.as_deref(), cli_args.run_dir_name.as_deref()),
+                None => {
+                    println!("A YAML config file path must be provided (`cargo make run -h`)");
+                    exit(2);
+                },
+            }
+            exit(0);
+        },
+        None => (),
+    }
+
     // Parse YAML config
-    if !Path::new(&cli_args.yaml_path).exists() {
-        println!("YAML file path does not exist: {}", cli_args.yaml_path);
+    let yaml_path = match cli_args.yaml_path {
+        Some(yaml_path) => yaml_path,
+        None => {
+            println!("A YAML config file path must be provided (`cargo make run -h`)");
+            exit(2);
+        },
+    };
+    if !Path::new(&yaml_path).exists() {
+        println!("YAML file path does not exist: {}", yaml_path);
         exit(2);
     }
-    init_global_conf(&cli_args.yaml_path);
+    init_global_conf(&yaml_path);
     let conf = borrow_global_conf().unwrap();
     #[cfg(not(feature = "multicore"))]
     println!("{:?}", conf);
 
+    // Tunnel discovery, replays and coverage reports run on a single core
+    #[cfg(feature = "multicore")]
+    if unsafe { TUNNEL_DISCOVERY.is_some() || REPLAY.is_some() || COVERAGE_REPORT.is_some() } {
+        println!("Tunnel discovery, replays and coverage reports are not supported with multicore fuzzing");
+        exit(3);
+    }
+
+    // The coverage report is per function of the symbol map
+    if unsafe { COVERAGE_REPORT.is_some() } {
+        match &conf.coverage_symbols {
+            Some(symbols) if Path::new(symbols).exists() => (),
+            Some(symbols) => {
+                println!("Symbol map path does not exist: {}", symbols);
+                exit(2);
+            },
+            None => {
+                println!("A symbol map must be provided in 'coverage: symbols:' for the coverage report");
+                exit(2);
+            },
+        }
+    }
+
     // For multicore fuzzing a core number must be provided
     #[cfg(feature = "multicore")]
     if cli_args.num_cores.is_some() {
@@ -651,11 +1234,45 @@ fn parse_args() -> Vec<String> {
         std::process::exit(7);
     }
 
+    // Resuming keeps the run directory, which must exist
+    if cli_args.resume {
+        if unsafe { TUNNEL_DISCOVERY.is_some() || REPLAY.is_some() || COVERAGE_REPORT.is_some() } {
+            println!("Only fuzzing campaigns can be resumed");
+            exit(9);
+        }
+        match cli_args.run_dir_name.as_ref() {
+            Some(run_dir_name) if Path::new(&format!("runs/{}", run_dir_name)).exists() => (),
+            Some(run_dir_name) => {
+                println!("Run directory does not exist: runs/{}", run_dir_name);
+                exit(9);
+            },
+            None => {
+                println!("A run directory name must be provided to resume (`-r {{run_dir_name}} --resume`)");
+                exit(9);
+            },
+        }
+        unsafe { RESUME = true; }
+    }
+
     // Use run directory if provided
     if cli_args.run_dir_name.is_some() {
         unsafe { RUN_DIR_NAME = Some(cli_args.run_dir_name.unwrap()); }
     }
 
+    // The run directory is recreated, the corpus must not be part of it
+    let corpus = unsafe {
+        TUNNEL_DISCOVERY.as_ref().map(|discovery| &discovery.0)
+            .or(REPLAY.as_ref().map(|replay| &replay.0))
+            .or(COVERAGE_REPORT.as_ref().map(|report| &report.0))
+    };
+    if let (Some(run_dir_name), Some(corpus)) = (unsafe { RUN_DIR_NAME.as_ref() }, corpus) {
+        let run_dir = PathBuf::from(format!("runs/{}", run_dir_name));
+        if corpus.iter().any(|path| Path::new(path).starts_with(&run_dir)) {
+            println!("Corpus must not be inside the run directory {:?}", run_dir);
+            exit(8);
+        }
+    }
+
     // Create arguments to start QEMU with
     let mut qemu_args: Vec<String> = vec![env::args().nth(0).unwrap()];
     #[cfg(feature = "multicore")]
@@ -0,0 +1,216 @@
+/// Generating a starter YAML config from a flash image
+/// The input regions, fixed pointers and fixups are derived from the directory tree,
+/// everything else is taken from the YAML config of the same Zen generation
+
+use libasp::*;
+
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+
+/* Default templates for the supported Zen generations */
+const TEMPLATES: [(&str, &str); 5] = [
+    ("Zen1",        "yaml/ryzen_zen1_desktop_parse_asp_flash.yaml"),
+    ("Zen+",        "yaml/ryzen_zen+_desktop_parse_asp_flash.yaml"),
+    ("Zen2",        "yaml/ryzen_zen2_desktop_parse_asp_flash.yaml"),
+    ("Zen3",        "yaml/ryzen_zen3_desktop_parse_asp_flash.yaml"),
+    ("ZenTesla",    "yaml/ryzen_zentesla_parse_asp_flash.yaml"),
+];
+
+/* Input regions for directories are rounded up to leave room for more entries */
+const DIR_REGION_ALIGN: usize = 0x100;
+
+pub fn gen_config(rom_path: &str, zen: &str, template_path: Option<&str>, output_path: Option<&str>) {
+    let template_path = match template_path {
+        Some(template_path) => template_path.to_string(),
+        None => match TEMPLATES.iter().find(|template| template.0 == zen) {
+            Some(template) => template.1.to_string(),
+            None => {
+                println!("{} generation not supported yet.", zen);
+                exit(6);
+            },
+        },
+    };
+    if !Path::new(&template_path).exists() {
+        println!("YAML template path does not exist: {}", template_path);
+        exit(2);
+    }
+    if !Path::new(rom_path).exists() {
+        println!("UEFI file path does not exist: {}", rom_path);
+        exit(5);
+    }
+    let template = fs::read_to_string(&template_path).expect("Unable to read yaml template");
+    let image = fs::read(rom_path).expect("Unable to read UEFI image");
+
+    // Parse the directory tree
+    let layout = FlashLayout::parse(&image);
+    if layout.fet.is_none() || layout.psp_directories().is_empty() {
+        println!("No PSP directory found in {}", rom_path);
+        exit(9);
+    }
+
+    // Keep every section of the template except for "flash" and "input"
+    let mut out_str = "".to_string();
+    for (key, section) in split_sections(&template) {
+        match key.as_str() {
+            "flash" => out_str.push_str(&flash_section(&section, rom_path, image.len())),
+            "input" => out_str.push_str(&input_section(&layout, rom_path)),
+            _       => out_str.push_str(&section),
+        }
+    }
+
+    match output_path {
+        Some(output_path) => fs::write(output_path, out_str).expect("Unable to write yaml config"),
+        None => print!("{}", out_str),
+    }
+}
+
+/* Splits a YAML file into top-level sections, each including the comments above it */
+fn split_sections(text: &str) -> Vec<(String, String)> {
+    let mut sections: Vec<(String, String)> = vec![("".to_string(), "".to_string())];
+    let mut pending = "".to_string();
+    for line in text.lines() {
+        if line.starts_with('#') || line.trim().is_empty() {
+            // Comments and empty lines belong to the next section
+            pending.push_str(line);
+            pending.push('\n');
+            continue;
+        }
+        if line.chars().next().map_or(false, |c| c.is_ascii_alphabetic()) && line.contains(':') {
+            let key = line.split(':').next().unwrap().trim().to_string();
+            sections.push((key, "".to_string()));
+        }
+        let section = sections.last_mut().unwrap();
+        section.1.push_str(&pending);
+        section.1.push_str(line);
+        section.1.push('\n');
+        pending.clear();
+    }
+    sections.last_mut().unwrap().1.push_str(&pending);
+    sections
+}
+
+/* Flash section with the SMN/CPU mapping of the template */
+fn flash_section(template: &str, rom_path: &str, rom_size: usize) -> String {
+    let mut out_str = "".to_string();
+    for line in template.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("size:") {
+            out_str.push_str(&format!("    size:           {:#010x}\n", rom_size));
+        } else if trimmed.starts_with("base:") {
+            out_str.push_str(&format!("    base:           \"{}\"\n", rom_path));
+        } else {
+            out_str.push_str(line);
+            out_str.push('\n');
+        }
+    }
+    out_str
+}
+
+fn input_section(layout: &FlashLayout, rom_path: &str) -> String {
+    let fet = layout.fet.unwrap();
+    let dirs = layout.psp_directories();
+
+    // Input regions: FET, PSP directories and the entry header of the PSP bootloader
+    let mut regions: Vec<(String, usize, usize)> = vec![("FET".to_string(), fet, FET_SIZE)];
+    for (i, dir) in dirs.iter().enumerate() {
+        let size = (dir.size() + DIR_REGION_ALIGN - 1) / DIR_REGION_ALIGN * DIR_REGION_ALIGN;
+        regions.push((format!("dir{} ({:?})", i, dir.kind), dir.offset, size.max(DIR_REGION_ALIGN)));
+    }
+    if let Some(addr) = layout.psp_entry(PSP_ENTRY_BOOTLOADER).and_then(|entry| entry.addr) {
+        regions.push(("Entry header".to_string(), addr, ENTRY_HEADER_SIZE));
+    }
+    // Regions must neither overlap nor leave the flash image
+    let starts: Vec<usize> = regions.iter().map(|region| region.1).collect();
+    for region in regions.iter_mut() {
+        for start in starts.iter() {
+            if *start > region.1 && *start < region.1 + region.2 {
+                region.2 = *start - region.1;
+            }
+        }
+        region.2 = region.2.min(layout.size - region.1);
+    }
+
+    let mut out_str = "".to_string();
+    out_str.push_str("\n# Input data and area\n");
+    out_str.push_str("# (generated from the directory tree of the flash image)\n");
+    out_str.push_str("input:\n");
+    out_str.push_str("    # Initial inputs for the fuzzer (UEFI images or directories of UEFI images)\n");
+    out_str.push_str("    initial:\n");
+    out_str.push_str(&format!("        -   \"{}\"\n", rom_path));
+    out_str.push_str("    # Input bytes in-order to flash memory\n");
+    out_str.push_str("    mem:\n");
+    for (name, addr, size) in regions.iter() {
+        out_str.push_str(&format!("        # {}\n", name));
+        out_str.push_str(&format!("        - addr:     {:#010x}\n", addr));
+        out_str.push_str(&format!("          size:     {:#x}\n", size));
+        let dir = dirs.iter().enumerate().find(|(_, dir)| dir.offset == *addr && *size >= dir.kind.header_size());
+        if let Some((i, dir)) = dir {
+            let prefix = format!("dir{}", i);
+            let header_size = dir.kind.header_size();
+            out_str.push_str("          layout:\n");
+            out_str.push_str(&format!("            - name:      \"{}.magic\"\n", prefix));
+            out_str.push_str("              offset:    0x0\n");
+            out_str.push_str("              width:     4\n");
+            out_str.push_str(&format!("              enum:      [{:#010x}]\n", dir.kind.magic()));
+            out_str.push_str(&format!("            - name:      \"{}.checksum\"\n", prefix));
+            out_str.push_str("              offset:    0x4\n");
+            out_str.push_str("              width:     4\n");
+            out_str.push_str(&format!("            - name:      \"{}.num_entries\"\n", prefix));
+            out_str.push_str("              offset:    0x8\n");
+            out_str.push_str("              width:     4\n");
+            if *size > header_size {
+                out_str.push_str(&format!("              length_of: \"{}.entries\"\n", prefix));
+                out_str.push_str(&format!("              unit:      {:#x}\n", dir.kind.entry_size()));
+                out_str.push_str(&format!("            - name:      \"{}.entries\"\n", prefix));
+                out_str.push_str(&format!("              offset:    {:#x}\n", header_size));
+                out_str.push_str(&format!("              width:     {:#x}\n", size - header_size));
+            }
+        }
+    }
+
+    // Fixed pointers: FET -> directory, directory -> public key, bootloader and sub directories
+    out_str.push_str("    # Set fixed values at certain flash addresses\n");
+    out_str.push_str("    fixed:\n");
+    if let Some((ptr, val)) = layout.fet_psp_dir {
+        out_str.push_str("        # FET: PSP Dir addr\n");
+        out_str.push_str(&format!("        - addr:     {:#010x}\n", ptr));
+        out_str.push_str(&format!("          val:      {:#010x}\n", val));
+    }
+    for (i, dir) in dirs.iter().enumerate() {
+        for entry in dir.entries.iter() {
+            let name = if dir.kind.is_combo() {
+                "Dir addr"
+            } else {
+                match entry.kind & 0xff {
+                    PSP_ENTRY_AMD_PUBKEY    => "Public key addr",
+                    PSP_ENTRY_BOOTLOADER    => "Entry header addr",
+                    PSP_ENTRY_SECONDARY_DIR => "Dir addr",
+                    _                       => continue,
+                }
+            };
+            // Only pointers which are part of the input need to be fixed
+            if !regions.iter().any(|region| entry.addr_field() >= region.1 && entry.addr_field() < region.1 + region.2) {
+                continue;
+            }
+            out_str.push_str(&format!("        # dir{}: {}\n", i, name));
+            out_str.push_str(&format!("        - addr:     {:#010x}\n", entry.addr_field()));
+            out_str.push_str(&format!("          val:      {:#010x}\n", entry.addr_raw as u32));
+        }
+    }
+
+    // Checksums of all directories
+    out_str.push_str("    # Recompute checksums before the input is written to flash memory\n");
+    out_str.push_str("    fixups:\n");
+    for (i, dir) in dirs.iter().enumerate() {
+        out_str.push_str(&format!("        # dir{} checksum\n", i));
+        out_str.push_str("        - kind:     \"fletcher32\"\n");
+        out_str.push_str(&format!("          begin:    {:#010x}\n", dir.offset + 0x8));
+        out_str.push_str(&format!("          size:     {:#x}\n", dir.kind.header_size() - 0x8));
+        out_str.push_str(&format!("          count:    {:#010x}\n", dir.offset + 0x8));
+        out_str.push_str(&format!("          stride:   {:#x}\n", dir.kind.entry_size()));
+        out_str.push_str(&format!("          dest:     {:#010x}\n", dir.offset + 0x4));
+    }
+    out_str.push_str("\n");
+    out_str
+}
@@ -1,5 +1,9 @@
 #[cfg(all(target_os = "linux", not(feature = "performance")))]
+mod differential;
+#[cfg(all(target_os = "linux", not(feature = "performance")))]
 mod fuzzer;
+#[cfg(all(target_os = "linux", not(feature = "performance")))]
+mod gen_config;
 #[cfg(all(target_os = "linux", feature = "performance"))]
 mod performance;
 
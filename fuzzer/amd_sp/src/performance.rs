@@ -83,6 +83,7 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
     }
     total_time = total_time/num_iter;
     write!(rs_file, "\nWhole flash parsing:\t\t\t{:>12}/iter\n", format!("{:?}", total_time)).unwrap();
+    let real_ccp_time = total_time;
 
     rs.load(&emu, &ResetLevel::HardReset);
     emu.set_breakpoint(unsafe {*DIR_OFFSET.as_ref().unwrap()});
@@ -182,6 +183,28 @@ extern "C" fn on_vcpu(mut cpu: CPU) {
     total_time = total_time/num_iter;
     write!(rs_file, "Until call off_chip function:\t\t{:>12}/iter\n", format!("{:?}", total_time)).unwrap();
 
+    // Flash parsing runtime with the CCP fast-path (stubs can't be removed, so measured last)
+    let ccp_verify = vec![
+        CcpVerify { addr: unsafe {*VERIFY_PUBKEY.as_ref().unwrap()}, valid: 0, invalid: 1 },
+        CcpVerify { addr: unsafe {*VERIFY_APP.as_ref().unwrap()}, valid: 0, invalid: 1 },
+    ];
+    add_ccp_fast_path(CcpMode::Valid, &ccp_verify, &emu);
+    rs.load(&emu, &ResetLevel::HardReset);
+    total_time = Duration::from_secs(0);
+    for _ in 0..num_iter {
+        let start = Instant::now();
+        emu.start(&cpu);
+        while tunnels_jumped() {
+            emu.start(&cpu);
+        }
+        let duration = start.elapsed();
+        total_time += duration;
+        rs.load(&emu, &ResetLevel::HardReset);
+    }
+    total_time = total_time/num_iter;
+    write!(rs_file, "\nWhole flash parsing (CCP fast-path):\t{:>12}/iter\n", format!("{:?}", total_time)).unwrap();
+    write!(rs_file, "CCP fast-path speedup:\t\t\t{:>12.2}x\n", real_ccp_time.as_secs_f64()/total_time.as_secs_f64()).unwrap();
+
     exit(0);
 }
 
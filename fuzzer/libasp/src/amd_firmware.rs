@@ -0,0 +1,403 @@
+/// Parsing the AMD firmware layout of a flash image
+/// Firmware Entry Table (FET), combo/PSP/BIOS directories and entry headers
+
+use log;
+use std::collections::HashSet;
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+
+pub const FET_SIGNATURE: u32        = 0x55aa_55aa;
+pub const FET_SIZE: usize           = 0x40;
+/* Possible FET locations in a 16 MiB image */
+pub const FET_OFFSETS: [usize; 6]   = [0xfa_0000, 0xf2_0000, 0xe2_0000, 0xc2_0000, 0x82_0000, 0x02_0000];
+/* FET pointers to the PSP directory (new/combo first) and BIOS directories */
+pub const FET_PSP_DIR_OFFSETS: [usize; 2]   = [0x14, 0x10];
+pub const FET_BIOS_DIR_OFFSETS: [usize; 3]  = [0x18, 0x1c, 0x20];
+
+pub const ENTRY_HEADER_SIZE: usize  = 0x100;
+
+/* PSP directory entry types */
+pub const PSP_ENTRY_AMD_PUBKEY: u32     = 0x00;
+pub const PSP_ENTRY_BOOTLOADER: u32     = 0x01;
+pub const PSP_ENTRY_SECONDARY_DIR: u32  = 0x40;
+/* BIOS directory entry types */
+pub const BIOS_ENTRY_SECONDARY_DIR: u32 = 0x70;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum DirectoryKind {
+    Psp,
+    PspLevel2,
+    PspCombo,
+    Bios,
+    BiosLevel2,
+    BiosCombo,
+}
+
+impl DirectoryKind {
+    pub fn from_magic(magic: u32) -> Option<Self> {
+        match magic {
+            0x5053_5024 => Some(DirectoryKind::Psp),        // "$PSP"
+            0x324c_5024 => Some(DirectoryKind::PspLevel2),  // "$PL2"
+            0x5053_5032 => Some(DirectoryKind::PspCombo),   // "2PSP"
+            0x4448_4224 => Some(DirectoryKind::Bios),       // "$BHD"
+            0x324c_4224 => Some(DirectoryKind::BiosLevel2), // "$BL2"
+            0x4448_4232 => Some(DirectoryKind::BiosCombo),  // "2BHD"
+            _           => None,
+        }
+    }
+
+    pub fn magic(&self) -> u32 {
+        match *self {
+            DirectoryKind::Psp          => 0x5053_5024,
+            DirectoryKind::PspLevel2    => 0x324c_5024,
+            DirectoryKind::PspCombo     => 0x5053_5032,
+            DirectoryKind::Bios         => 0x4448_4224,
+            DirectoryKind::BiosLevel2   => 0x324c_4224,
+            DirectoryKind::BiosCombo    => 0x4448_4232,
+        }
+    }
+
+    pub fn header_size(&self) -> usize {
+        if self.is_combo() { 0x20 } else { 0x10 }
+    }
+
+    pub fn entry_size(&self) -> usize {
+        match *self {
+            DirectoryKind::Bios | DirectoryKind::BiosLevel2 => 0x18,
+            _                                               => 0x10,
+        }
+    }
+
+    pub fn is_combo(&self) -> bool {
+        *self == DirectoryKind::PspCombo || *self == DirectoryKind::BiosCombo
+    }
+
+    pub fn is_psp(&self) -> bool {
+        *self == DirectoryKind::Psp || *self == DirectoryKind::PspLevel2 || *self == DirectoryKind::PspCombo
+    }
+}
+
+/*
+ *  Entry layouts (all little endian):
+ *  - PSP:   type(4), size(4), addr(8)
+ *  - BIOS:  type(4), size(4), source(8), destination(8)
+ *  - Combo: id_select(4), id(4), addr(8)
+ *  The address field is always at offset 0x8
+ */
+#[derive(Clone)]
+pub struct DirectoryEntry {
+    pub offset:     usize,
+    pub kind:       u32,
+    pub size:       u32,
+    pub addr_raw:   u64,
+    pub addr:       Option<usize>,
+}
+
+impl DirectoryEntry {
+    pub const ADDR_OFFSET: usize = 0x8;
+
+    /// Flash offset of the address field of this entry
+    pub fn addr_field(&self) -> usize {
+        self.offset + Self::ADDR_OFFSET
+    }
+}
+
+#[derive(Clone)]
+pub struct Directory {
+    pub kind:           DirectoryKind,
+    pub offset:         usize,
+    pub checksum:       u32,
+    pub num_entries:    u32,
+    pub entries:        Vec<DirectoryEntry>,
+}
+
+impl Directory {
+    /// Size of the header and all entries
+    pub fn size(&self) -> usize {
+        self.kind.header_size() + self.entries.len() * self.kind.entry_size()
+    }
+
+    pub fn entry(&self, kind: u32) -> Option<&DirectoryEntry> {
+        self.entries.iter().find(|entry| (entry.kind & 0xff) == kind)
+    }
+}
+
+pub struct FlashLayout {
+    pub size:           usize,
+    pub fet:            Option<usize>,
+    /* FET pointer to the first PSP directory: (flash offset, raw value) */
+    pub fet_psp_dir:    Option<(usize, u32)>,
+    pub directories:    Vec<Directory>,
+}
+
+impl FlashLayout {
+    /// Parses the directory tree starting at the FET
+    pub fn parse(image: &[u8]) -> Self {
+        let mut layout = Self {
+            size:           image.len(),
+            fet:            None,
+            fet_psp_dir:    None,
+            directories:    vec![],
+        };
+        if !image.len().is_power_of_two() {
+            log::warn!("Flash image size {:#x} is not a power of two", image.len());
+        }
+
+        // Firmware Entry Table
+        for fet in FET_OFFSETS.iter() {
+            let fet = *fet & (image.len().next_power_of_two() - 1);
+            if read_u32(image, fet) == Some(FET_SIGNATURE) {
+                layout.fet = Some(fet);
+                break;
+            }
+        }
+        let fet = match layout.fet {
+            Some(fet) => fet,
+            None => return layout,
+        };
+
+        // Directories reachable from the FET
+        let mut visited = HashSet::new();
+        for ptr in FET_PSP_DIR_OFFSETS.iter().chain(FET_BIOS_DIR_OFFSETS.iter()) {
+            let raw = match read_u32(image, fet + *ptr) {
+                Some(raw) => raw,
+                None => continue,
+            };
+            if let Some(dir) = layout.to_offset(raw as u64, 0) {
+                let found = layout.parse_directory(image, dir, &mut visited);
+                if found && layout.fet_psp_dir.is_none() && FET_PSP_DIR_OFFSETS.contains(ptr) {
+                    layout.fet_psp_dir = Some((fet + *ptr, raw));
+                }
+            }
+        }
+        layout
+    }
+
+    /* Parses a directory and all directories it points to, returns false if there is none */
+    fn parse_directory(&mut self, image: &[u8], offset: usize, visited: &mut HashSet<usize>) -> bool {
+        if !visited.insert(offset) {
+            return true;
+        }
+        let kind = match read_u32(image, offset).and_then(DirectoryKind::from_magic) {
+            Some(kind) => kind,
+            None => return false,
+        };
+        let checksum = read_u32(image, offset + 0x4).unwrap_or(0);
+        let num_entries = read_u32(image, offset + 0x8).unwrap_or(0);
+
+        let mut entries = vec![];
+        for i in 0..num_entries as usize {
+            let entry = offset + kind.header_size() + i * kind.entry_size();
+            let (entry_kind, entry_size, addr_raw) = match (
+                read_u32(image, entry),
+                read_u32(image, entry + 0x4),
+                read_u64(image, entry + DirectoryEntry::ADDR_OFFSET),
+            ) {
+                (Some(entry_kind), Some(entry_size), Some(addr_raw)) => (entry_kind, entry_size, addr_raw),
+                _ => break,
+            };
+            entries.push(DirectoryEntry {
+                offset:     entry,
+                kind:       entry_kind,
+                size:       entry_size,
+                addr_raw:   addr_raw,
+                addr:       self.to_offset(addr_raw, offset),
+            });
+        }
+        if entries.len() != num_entries as usize {
+            log::warn!("Directory at {:#x} is truncated", offset);
+        }
+
+        let sub_dirs: Vec<usize> = entries.iter().filter(|entry|
+            kind.is_combo() ||
+            (kind.is_psp() && (entry.kind & 0xff) == PSP_ENTRY_SECONDARY_DIR) ||
+            (!kind.is_psp() && (entry.kind & 0xff) == BIOS_ENTRY_SECONDARY_DIR)
+        ).filter_map(|entry| entry.addr).collect();
+        self.directories.push(Directory {
+            kind:           kind,
+            offset:         offset,
+            checksum:       checksum,
+            num_entries:    num_entries,
+            entries:        entries,
+        });
+        for sub_dir in sub_dirs {
+            self.parse_directory(image, sub_dir, visited);
+        }
+        true
+    }
+
+    /*
+     *  Addresses are either x86 physical addresses (0xffxxxxxx) or flash offsets
+     *  The two upper bits of 64-bit addresses select the addressing mode:
+     *  - 0: physical address, 1: flash offset, 2: relative to the directory, 3: relative to the slot
+     */
+    pub fn to_offset(&self, raw: u64, dir_offset: usize) -> Option<usize> {
+        let addr = (raw & 0xffff_ffff) as usize;
+        if addr == 0 || addr == 0xffff_ffff {
+            return None;
+        }
+        let mask = self.size.next_power_of_two() - 1;
+        let offset = match raw >> 62 {
+            2 => (dir_offset + addr) & mask,
+            _ => addr & mask,
+        };
+        if offset >= self.size {
+            return None;
+        }
+        Some(offset)
+    }
+
+    /// All PSP directories in the order they are reached from the FET
+    pub fn psp_directories(&self) -> Vec<&Directory> {
+        self.directories.iter().filter(|dir| dir.kind.is_psp()).collect()
+    }
+
+    /// First entry of the given type in any PSP directory
+    pub fn psp_entry(&self, kind: u32) -> Option<&DirectoryEntry> {
+        self.psp_directories().into_iter().find_map(|dir| {
+            if dir.kind.is_combo() { None } else { dir.entry(kind) }
+        })
+    }
+}
+
+pub fn read_u32(image: &[u8], offset: usize) -> Option<u32> {
+    image.get(offset..offset+4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+pub fn read_u64(image: &[u8], offset: usize) -> Option<u64> {
+    image.get(offset..offset+8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+impl Debug for DirectoryKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match *self {
+            DirectoryKind::Psp          => write!(f, "$PSP"),
+            DirectoryKind::PspLevel2    => write!(f, "$PL2"),
+            DirectoryKind::PspCombo     => write!(f, "2PSP"),
+            DirectoryKind::Bios         => write!(f, "$BHD"),
+            DirectoryKind::BiosLevel2   => write!(f, "$BL2"),
+            DirectoryKind::BiosCombo    => write!(f, "2BHD"),
+        }
+    }
+}
+
+impl Debug for FlashLayout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        let mut out_str = "".to_string();
+        out_str.push_str(&format!("#### Flash layout ({:#x} bytes) ####\n", self.size));
+        match self.fet {
+            Some(fet) => out_str.push_str(&format!("FET:\t{:#010x}\n", fet)),
+            None => out_str.push_str(&format!("FET:\tnot found\n")),
+        }
+        for dir in self.directories.iter() {
+            out_str.push_str(&format!("{:?}:\t{:#010x} ({} entries, checksum {:#010x})\n",
+                dir.kind, dir.offset, dir.num_entries, dir.checksum));
+            for entry in dir.entries.iter() {
+                out_str.push_str(&format!("\t{:#010x}: type {:#06x}, size {:#010x}, addr {:#018x}",
+                    entry.offset, entry.kind, entry.size, entry.addr_raw));
+                if let Some(addr) = entry.addr {
+                    out_str.push_str(&format!(" -> {:#010x}", addr));
+                }
+                out_str.push_str("\n");
+            }
+        }
+        write!(f, "{}", out_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE_SIZE: usize = 0x4_0000;
+
+    fn write_u32(image: &mut [u8], offset: usize, value: u32) {
+        image[offset..offset+4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_u64(image: &mut [u8], offset: usize, value: u64) {
+        image[offset..offset+8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /* PSP directory with entries of (type, raw address) */
+    fn write_dir(image: &mut [u8], offset: usize, kind: DirectoryKind, entries: &[(u32, u64)]) {
+        write_u32(image, offset, kind.magic());
+        write_u32(image, offset + 0x8, entries.len() as u32);
+        for (i, (entry_kind, addr_raw)) in entries.iter().enumerate() {
+            let entry = offset + kind.header_size() + i * kind.entry_size();
+            write_u32(image, entry, *entry_kind);
+            write_u32(image, entry + 0x4, 0x100);
+            write_u64(image, entry + DirectoryEntry::ADDR_OFFSET, *addr_raw);
+        }
+    }
+
+    /* FET at 0x20000 (0xfa0000 masked to the image size) pointing to a PSP directory */
+    fn image_with_fet(psp_dir_raw: u32) -> Vec<u8> {
+        let mut image = vec![0xff; IMAGE_SIZE];
+        write_u32(&mut image, 0x2_0000, FET_SIGNATURE);
+        write_u32(&mut image, 0x2_0000 + FET_PSP_DIR_OFFSETS[0], psp_dir_raw);
+        image
+    }
+
+    #[test]
+    fn parse_directory_tree() {
+        let mut image = image_with_fet(0xff03_0000);
+        write_dir(&mut image, 0x3_0000, DirectoryKind::Psp, &[
+            // Directory-relative bootloader
+            (PSP_ENTRY_BOOTLOADER, (2 << 62) | 0x1000),
+            // Level 2 directory by flash offset
+            (PSP_ENTRY_SECONDARY_DIR, (1 << 62) | 0x3_8000),
+        ]);
+        write_dir(&mut image, 0x3_8000, DirectoryKind::PspLevel2, &[(PSP_ENTRY_AMD_PUBKEY, 0xff03_9000)]);
+
+        let layout = FlashLayout::parse(&image);
+        assert_eq!(layout.fet, Some(0x2_0000));
+        assert_eq!(layout.fet_psp_dir, Some((0x2_0000 + FET_PSP_DIR_OFFSETS[0], 0xff03_0000)));
+        let dirs: Vec<(DirectoryKind, usize)> = layout.directories.iter().map(|dir| (dir.kind, dir.offset)).collect();
+        assert!(dirs == vec![(DirectoryKind::Psp, 0x3_0000), (DirectoryKind::PspLevel2, 0x3_8000)]);
+        assert_eq!(layout.psp_entry(PSP_ENTRY_BOOTLOADER).and_then(|entry| entry.addr), Some(0x3_1000));
+        assert_eq!(layout.psp_entry(PSP_ENTRY_AMD_PUBKEY).and_then(|entry| entry.addr), Some(0x3_9000));
+        assert_eq!(layout.directories[0].entries[1].addr_field(), 0x3_0000 + 0x20 + DirectoryEntry::ADDR_OFFSET);
+        assert_eq!(layout.directories[0].size(), 0x30);
+    }
+
+    #[test]
+    fn parse_without_fet() {
+        let layout = FlashLayout::parse(&vec![0xff; IMAGE_SIZE]);
+        assert_eq!(layout.fet, None);
+        assert!(layout.directories.is_empty());
+    }
+
+    #[test]
+    fn parse_directory_loop() {
+        // A directory listing itself as its level 2 directory is parsed once
+        let mut image = image_with_fet(0x3_0000);
+        write_dir(&mut image, 0x3_0000, DirectoryKind::Psp, &[(PSP_ENTRY_SECONDARY_DIR, 0x3_0000)]);
+        let layout = FlashLayout::parse(&image);
+        assert_eq!(layout.directories.len(), 1);
+    }
+
+    #[test]
+    fn parse_truncated_directory() {
+        // More entries than fit into the image
+        let mut image = image_with_fet(0x3_ffe0);
+        write_u32(&mut image, 0x3_ffe0, DirectoryKind::Psp.magic());
+        write_u32(&mut image, 0x3_ffe8, 4);
+        let layout = FlashLayout::parse(&image);
+        assert_eq!(layout.directories.len(), 1);
+        assert_eq!(layout.directories[0].num_entries, 4);
+        assert_eq!(layout.directories[0].entries.len(), 1);
+    }
+
+    #[test]
+    fn to_offset_modes() {
+        let layout = FlashLayout::parse(&vec![0xff; IMAGE_SIZE]);
+        assert_eq!(layout.to_offset(0, 0), None);
+        assert_eq!(layout.to_offset(0xffff_ffff, 0), None);
+        assert_eq!(layout.to_offset(0xff03_1000, 0), Some(0x3_1000));
+        assert_eq!(layout.to_offset((1 << 62) | 0x3_1000, 0), Some(0x3_1000));
+        assert_eq!(layout.to_offset((2 << 62) | 0x1000, 0x3_0000), Some(0x3_1000));
+    }
+}
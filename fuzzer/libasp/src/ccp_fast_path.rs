@@ -0,0 +1,74 @@
+/// Fast-path for the CCP (crypto coprocessor) backed signature verification
+/// The verification functions return a configured result without running the emulated crypto
+
+use crate::stubs::*;
+
+use libafl_qemu::*;
+
+use log;
+use std::str::FromStr;
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+
+#[derive(Copy, Clone, PartialEq, Default)]
+pub enum CcpMode {
+    // Emulated CCP
+    #[default]
+    Real,
+    Valid,
+    Invalid,
+}
+
+/* Entry of a verification function with its return values */
+#[derive(Copy, Clone)]
+pub struct CcpVerify {
+    pub addr:       GuestAddr,
+    pub valid:      u32,
+    pub invalid:    u32,
+}
+
+pub fn add_ccp_fast_path(mode: CcpMode, verify: &Vec<CcpVerify>, emu: &Emulator) {
+    for func in verify.iter() {
+        let ret = match mode {
+            CcpMode::Real       => return,
+            CcpMode::Valid      => func.valid,
+            CcpMode::Invalid    => func.invalid,
+        };
+        log::info!("CCP fast-path: {:#010x} returns {:#x}", func.addr, ret);
+        add_stub(&Stub {
+            addr:   func.addr,
+            buf:    None,
+            ret:    StubReturn::Value(ret),
+        }, emu);
+    }
+}
+
+impl Debug for CcpMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match *self {
+            CcpMode::Real       => write!(f, "real"),
+            CcpMode::Valid      => write!(f, "valid"),
+            CcpMode::Invalid    => write!(f, "invalid"),
+        }
+    }
+}
+
+impl FromStr for CcpMode {
+    type Err = ();
+    fn from_str(input: &str) -> Result<CcpMode, ()> {
+        match input {
+            "real"      => Ok(CcpMode::Real),
+            "valid"     => Ok(CcpMode::Valid),
+            "invalid"   => Ok(CcpMode::Invalid),
+            _           => Err(()),
+        }
+    }
+}
+
+impl Debug for CcpVerify {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        write!(f, "({:#010x},valid={:#x},invalid={:#x})", self.addr, self.valid, self.invalid)
+    }
+}
@@ -0,0 +1,153 @@
+/// CmpLog instrumentation of the on-chip bootloader (ARM mode)
+/// Operands of CMP/CMN/TST/TEQ are logged to the CmpLog map for the input-to-state stage.
+/// Only ARM encodings are decoded: Thumb code and compare-and-branch (CBZ/CBNZ) are not logged,
+/// the input-to-state stage doesn't see those comparisons
+
+use libafl_qemu::*;
+use libafl_qemu::cmplog::{
+    __libafl_targets_cmplog_instructions,
+    CMPLOG_MAP_W,
+};
+
+use log;
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum CmpOp {
+    Tst,
+    Teq,
+    Cmp,
+    Cmn,
+}
+
+impl CmpOp {
+    /// Whether the Z flag would be set for the operands
+    pub fn is_zero(&self, v0: u32, v1: u32) -> bool {
+        match *self {
+            CmpOp::Tst  => v0 & v1 == 0,
+            CmpOp::Teq  => v0 ^ v1 == 0,
+            CmpOp::Cmp  => v0 == v1,
+            CmpOp::Cmn  => v0.wrapping_add(v1) == 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum CmpOperand {
+    Reg(u32),
+    Imm(u32),
+}
+
+#[derive(Copy, Clone)]
+pub struct CmpSite {
+    pub addr:       GuestAddr,
+    pub op:         CmpOp,
+    pub rn:         u32,
+    pub operand:    CmpOperand,
+}
+
+/* Sorted by address */
+static mut CMPLOG_SITES: Vec<CmpSite> = vec![];
+
+/* Set by the CmpLog observer of libafl_targets during the executions of the tracing stage */
+extern "C" {
+    static mut libafl_cmplog_enabled: u8;
+}
+
+/*
+ *  Logged comparisons (no shifted register operands):
+ *  - CMP/CMN/TST/TEQ Rn, #imm
+ *  - CMP/CMN/TST/TEQ Rn, Rm
+ *  Thumb code and compare-and-branch (CBZ/CBNZ) are not handled
+ */
+pub fn find_cmp_sites(bin: &[u8], base: GuestAddr) -> Vec<CmpSite> {
+    let mut sites = vec![];
+    for (i, insn) in bin.chunks_exact(4).enumerate() {
+        let insn = u32::from_le_bytes(insn.try_into().unwrap());
+        // Unconditional/special instructions
+        if insn >> 28 == 0xf {
+            continue;
+        }
+        let operand = if (insn & 0x0f90_0000) == 0x0310_0000 {
+            // Rn, #imm
+            let rot = ((insn >> 8) & 0xf) * 2;
+            CmpOperand::Imm((insn & 0xff).rotate_right(rot))
+        } else if (insn & 0x0f90_0ff0) == 0x0110_0000 {
+            // Rn, Rm
+            CmpOperand::Reg(insn & 0xf)
+        } else {
+            continue;
+        };
+        let op = match (insn >> 21) & 0x3 {
+            0 => CmpOp::Tst,
+            1 => CmpOp::Teq,
+            2 => CmpOp::Cmp,
+            _ => CmpOp::Cmn,
+        };
+        let rn = (insn >> 16) & 0xf;
+        // PC relative operands are never input-derived
+        if rn == 15 || matches!(operand, CmpOperand::Reg(15)) {
+            continue;
+        }
+        sites.push(CmpSite {
+            addr:       base + (i * 4) as GuestAddr,
+            op:         op,
+            rn:         rn,
+            operand:    operand,
+        });
+    }
+    sites
+}
+
+pub fn add_cmplog_hooks(bin: &[u8], base: GuestAddr, emu: &Emulator) {
+    let sites = find_cmp_sites(bin, base);
+    log::info!("Adding {} CmpLog hooks", sites.len());
+    for site in sites.iter() {
+        emu.set_hook(site.addr, cmplog_hook, emu as *const _ as u64, false);
+    }
+    unsafe { CMPLOG_SITES = sites; }
+}
+
+extern "C" fn cmplog_hook(pc: GuestAddr, data: u64) {
+    // Only the tracing stage logs comparisons
+    if unsafe { libafl_cmplog_enabled } == 0 {
+        return;
+    }
+    let emu = unsafe { (data as *const Emulator).as_ref().unwrap() };
+    let site = unsafe {
+        match CMPLOG_SITES.binary_search_by_key(&pc, |site| site.addr) {
+            Ok(i) => CMPLOG_SITES[i],
+            Err(_) => return,
+        }
+    };
+    let v0: u32 = emu.read_reg(site.rn as i32).unwrap();
+    let v1: u32 = match site.operand {
+        CmpOperand::Reg(rm) => emu.read_reg(rm as i32).unwrap(),
+        CmpOperand::Imm(imm) => imm,
+    };
+    let k = ((pc >> 2) ^ (pc >> 12)) as usize & (CMPLOG_MAP_W - 1);
+    unsafe { __libafl_targets_cmplog_instructions(k, 4, v0 as u64, v1 as u64); }
+}
+
+impl Debug for CmpOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match *self {
+            CmpOp::Tst  => write!(f, "tst"),
+            CmpOp::Teq  => write!(f, "teq"),
+            CmpOp::Cmp  => write!(f, "cmp"),
+            CmpOp::Cmn  => write!(f, "cmn"),
+        }
+    }
+}
+
+impl Debug for CmpSite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match self.operand {
+            CmpOperand::Reg(rm) => write!(f, "{:#010x}: {:?} r{}, r{}", self.addr, self.op, self.rn, rm),
+            CmpOperand::Imm(imm) => write!(f, "{:#010x}: {:?} r{}, #{:#x}", self.addr, self.op, self.rn, imm),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+/// Checking the destination of copy-like functions (memcpy, flash read, DMA helpers)
+/// At the entry of each function the destination range is read from its arguments and checked
+/// against protected regions, unless the function was called from an allowed caller
+
+use crate::stubs::read_arg;
+
+use libafl_qemu::*;
+use libafl::prelude::*;
+
+use log;
+use serde::{Deserialize, Serialize};
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+
+#[derive(Clone)]
+pub enum CopyLen {
+    // N-th argument (R0-R3, then stack)
+    Arg(usize),
+    Const(u32),
+}
+
+/* Region [begin, end) which may only be written by the allowed callers (LR) */
+#[derive(Clone)]
+pub struct CopyProtected {
+    pub begin:      GuestAddr,
+    pub end:        GuestAddr,
+    pub allowed:    Vec<GuestAddr>,
+}
+
+/*
+ *  A copy-like function at addr:
+ *  - src:      N-th argument with the source, only logged
+ *  - dest:     N-th argument with the destination
+ *  - len:      N-th argument with the length or a constant length
+ */
+#[derive(Clone)]
+pub struct CopyFn {
+    pub name:       String,
+    pub addr:       GuestAddr,
+    pub src:        Option<usize>,
+    pub dest:       usize,
+    pub len:        CopyLen,
+    pub protected:  Vec<CopyProtected>,
+}
+
+#[derive(Clone)]
+pub struct CopyViolation {
+    pub name:       String,
+    pub caller:     GuestAddr,
+    pub dest:       GuestAddr,
+    pub len:        u32,
+    pub region:     (GuestAddr, GuestAddr),
+}
+
+/* Copy function compiled at setup, passed to its hook as data */
+struct CopyAction {
+    emu:        *const Emulator,
+    copy_fn:    CopyFn,
+}
+
+static mut COPY_VIOLATION: Option<CopyViolation> = None;
+
+/// Has to be called before each execution
+pub fn copy_fns_reset() {
+    unsafe { COPY_VIOLATION = None; }
+}
+
+pub fn copy_fn_violation() -> Option<CopyViolation> {
+    unsafe { COPY_VIOLATION.clone() }
+}
+
+pub fn add_copy_fn(copy_fn: &CopyFn, emu: &Emulator) {
+    let action = CopyAction {
+        emu:        emu as *const _,
+        copy_fn:    copy_fn.clone(),
+    };
+    log::debug!("Adding copy function: {:?}", copy_fn);
+    // Hooks are never removed, the action lives as long as the emulator
+    let data = Box::into_raw(Box::new(action)) as u64;
+    emu.set_hook(copy_fn.addr, copy_fn_hook, data, false);
+}
+
+/* Half-open ranges overlap in any direction, including one containing the other */
+fn overlaps(a: (GuestAddr, GuestAddr), b: (GuestAddr, GuestAddr)) -> bool {
+    a.0 < b.1 && b.0 < a.1
+}
+
+extern "C" fn copy_fn_hook(pc: GuestAddr, data: u64) {
+    let action = unsafe { (data as *const CopyAction).as_ref().unwrap() };
+    let emu = unsafe { action.emu.as_ref().unwrap() };
+    let copy_fn = &action.copy_fn;
+    let dest = read_arg(emu, copy_fn.dest);
+    let len = match copy_fn.len {
+        CopyLen::Arg(arg)   => read_arg(emu, arg),
+        CopyLen::Const(len) => len,
+    };
+    if len == 0 {
+        return;
+    }
+    let caller: GuestAddr = emu.read_reg(Regs::Lr).unwrap();
+    let dest_end = dest.saturating_add(len);
+    if let Some(src) = copy_fn.src {
+        log::debug!("{} at {:#010x} from {:#010x} to {:#010x} for {:#x} bytes",
+            copy_fn.name, pc, read_arg(emu, src), dest, len);
+    }
+    for region in copy_fn.protected.iter() {
+        if !overlaps((dest, dest_end), (region.begin, region.end)) {
+            continue;
+        }
+        log::debug!("{} writes to [{:#010x}, {:#010x}) from {:#010x}", copy_fn.name, region.begin, region.end, caller);
+        if region.allowed.contains(&caller) {
+            continue;
+        }
+        let violation = CopyViolation {
+            name:   copy_fn.name.clone(),
+            caller: caller,
+            dest:   dest,
+            len:    len,
+            region: (region.begin, region.end),
+        };
+        log::info!("Copy function hook triggered: {:?}", violation);
+        unsafe { COPY_VIOLATION = Some(violation); }
+        emu.current_cpu().unwrap().trigger_breakpoint();
+        return;
+    }
+}
+
+/// Metadata of a copy into a protected region
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CopyMetadata {
+    pub violation: String,
+}
+
+impl_serdeany!(CopyMetadata);
+
+impl Debug for CopyFn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        write!(f, "({},{:#010x},", self.name, self.addr)?;
+        if let Some(src) = self.src {
+            write!(f, "src=arg{},", src)?;
+        }
+        write!(f, "dest=arg{},", self.dest)?;
+        match self.len {
+            CopyLen::Arg(arg)   => write!(f, "len=arg{}", arg),
+            CopyLen::Const(len) => write!(f, "len={:#x}", len),
+        }?;
+        for region in self.protected.iter() {
+            write!(f, ",[{:#010x},{:#010x})", region.begin, region.end)?;
+            for caller in region.allowed.iter() {
+                write!(f, " allow {:#010x}", caller)?;
+            }
+        }
+        write!(f, ")")
+    }
+}
+
+impl Debug for CopyViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        write!(f, "{} called from {:#010x} copies to [{:#010x}, {:#010x}) overlapping [{:#010x}, {:#010x})",
+            self.name, self.caller, self.dest, self.dest.saturating_add(self.len), self.region.0, self.region.1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlap_in_every_direction() {
+        let region = (0x100, 0x200);
+        // Ends inside, starts inside, contained, containing
+        assert!(overlaps((0x080, 0x101), region));
+        assert!(overlaps((0x1ff, 0x280), region));
+        assert!(overlaps((0x140, 0x180), region));
+        assert!(overlaps((0x000, 0x300), region));
+        assert!(overlaps(region, region));
+    }
+
+    #[test]
+    fn adjacent_ranges_do_not_overlap() {
+        let region = (0x100, 0x200);
+        assert!(!overlaps((0x080, 0x100), region));
+        assert!(!overlaps((0x200, 0x280), region));
+        assert!(!overlaps((0x000, 0x010), region));
+    }
+
+    #[test]
+    fn saturated_copy_overlaps_the_end_of_memory() {
+        // dest + len saturates at the top of the address space
+        let dest: GuestAddr = 0xffff_ff00;
+        assert!(overlaps((dest, dest.saturating_add(0x1000)), (0xffff_fff0, 0xffff_ffff)));
+    }
+}
@@ -0,0 +1,141 @@
+/// Coverage filtering by address ranges or symbols and the DrCov module map
+/// Builds the instrumentation filter of the edge coverage and the DrCov helpers from the
+/// "coverage:" section, deny ranges are cut out of the allow ranges, and the DrCov modules
+/// from the on-chip bootloader and the "drcov:" section
+
+use crate::symbols::SymbolMap;
+use crate::yaml_conf::YAMLConfig;
+
+use libafl_qemu::*;
+
+use log;
+use rangemap::RangeMap;
+use std::fs;
+use std::path::Path;
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+
+#[derive(Clone)]
+pub enum CoverageRange {
+    // [begin, end)
+    Addr(GuestAddr, GuestAddr),
+    Symbol(String),
+}
+
+fn resolve(ranges: &[CoverageRange], symbols: Option<&SymbolMap>) -> Vec<(GuestAddr, GuestAddr)> {
+    let mut resolved = vec![];
+    for range in ranges.iter() {
+        match range {
+            CoverageRange::Addr(begin, end) => resolved.push((*begin, *end)),
+            CoverageRange::Symbol(name) => {
+                let symbols = symbols.expect("Expecting 'coverage: symbols:' in yaml for symbol names");
+                resolved.push(symbols.range(name).unwrap_or_else(|| panic!("Unknown symbol {} in 'coverage:'", name)));
+            },
+        }
+    }
+    resolved
+}
+
+/* Cuts the deny ranges out of the allow ranges */
+fn subtract(allow: Vec<(GuestAddr, GuestAddr)>, deny: &[(GuestAddr, GuestAddr)]) -> Vec<(GuestAddr, GuestAddr)> {
+    let mut ranges = allow;
+    for cut in deny.iter() {
+        let mut next = vec![];
+        for range in ranges.into_iter() {
+            if cut.1 <= range.0 || range.1 <= cut.0 {
+                next.push(range);
+                continue;
+            }
+            if range.0 < cut.0 {
+                next.push((range.0, cut.0));
+            }
+            if cut.1 < range.1 {
+                next.push((cut.1, range.1));
+            }
+        }
+        ranges = next;
+    }
+    ranges
+}
+
+pub fn coverage_filter(conf: &YAMLConfig) -> QemuInstrumentationFilter {
+    let symbols = conf.coverage_symbols.as_ref().map(|path| SymbolMap::load(path));
+    let allow = resolve(&conf.coverage_allow, symbols.as_ref());
+    let deny = resolve(&conf.coverage_deny, symbols.as_ref());
+    if !allow.is_empty() {
+        let ranges = subtract(allow, &deny);
+        log::info!("Coverage of {} ranges", ranges.len());
+        QemuInstrumentationFilter::AllowList(ranges.iter().map(|range| range.0..range.1).collect())
+    } else if !deny.is_empty() {
+        log::info!("Coverage without {} ranges", deny.len());
+        QemuInstrumentationFilter::DenyList(deny.iter().map(|range| range.0..range.1).collect())
+    } else {
+        QemuInstrumentationFilter::None
+    }
+}
+
+/// Resolved ranges of "coverage: allow:"
+pub fn coverage_allow_ranges(conf: &YAMLConfig, symbols: &SymbolMap) -> Vec<(GuestAddr, GuestAddr)> {
+    resolve(&conf.coverage_allow, Some(symbols))
+}
+
+/// DrCov modules: the on-chip bootloader named after its file and the modules of "drcov:"
+pub fn drcov_modules(conf: &YAMLConfig, on_chip_addr: GuestAddr) -> RangeMap<usize, (u16, String)> {
+    let on_chip_path = Path::new(&conf.qemu_on_chip_bl_path);
+    let on_chip_size = fs::metadata(on_chip_path).unwrap().len() as usize;
+    let on_chip_name = on_chip_path.file_name().unwrap().to_string_lossy().to_string();
+    let mut modules = vec![(on_chip_name, on_chip_addr as usize, on_chip_addr as usize + on_chip_size)];
+    for module in conf.drcov_modules.iter() {
+        modules.push((module.0.clone(), module.1 as usize, module.2 as usize));
+    }
+    let mut rangemap = RangeMap::new();
+    for (id, module) in modules.into_iter().enumerate() {
+        if rangemap.overlaps(&(module.1..module.2)) {
+            panic!("DrCov module {} [{:#010x}, {:#010x}) overlaps another module", module.0, module.1, module.2);
+        }
+        log::info!("DrCov module {}: {} [{:#010x}, {:#010x})", id, module.0, module.1, module.2);
+        rangemap.insert(module.1..module.2, (id as u16, module.0));
+    }
+    rangemap
+}
+
+impl Debug for CoverageRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match self {
+            CoverageRange::Addr(begin, end) => write!(f, "[{:#010x},{:#010x})", begin, end),
+            CoverageRange::Symbol(name)     => write!(f, "{}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subtract_cuts_holes() {
+        let allow = vec![(0x100, 0x200), (0x300, 0x400)];
+        // Inside the first range, over the end of the first and the start of the second
+        assert_eq!(subtract(allow.clone(), &[(0x140, 0x180)]), vec![(0x100, 0x140), (0x180, 0x200), (0x300, 0x400)]);
+        assert_eq!(subtract(allow.clone(), &[(0x1c0, 0x340)]), vec![(0x100, 0x1c0), (0x340, 0x400)]);
+    }
+
+    #[test]
+    fn subtract_whole_and_disjoint_ranges() {
+        let allow = vec![(0x100, 0x200)];
+        assert_eq!(subtract(allow.clone(), &[(0x000, 0x300)]), vec![]);
+        assert_eq!(subtract(allow.clone(), &[(0x100, 0x200)]), vec![]);
+        // Adjacent deny ranges leave the allow range intact
+        assert_eq!(subtract(allow.clone(), &[(0x000, 0x100), (0x200, 0x300)]), allow);
+        assert_eq!(subtract(allow.clone(), &[]), allow);
+    }
+
+    #[test]
+    fn resolve_symbols() {
+        let symbols = SymbolMap::parse("0xffff0000 reset 0x10\n");
+        let ranges = [CoverageRange::Addr(0x100, 0x200), CoverageRange::Symbol("reset".to_string())];
+        assert_eq!(resolve(&ranges, Some(&symbols)), vec![(0x100, 0x200), (0xffff_0000, 0xffff_0010)]);
+    }
+}
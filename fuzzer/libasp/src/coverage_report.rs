@@ -0,0 +1,312 @@
+/// Aggregated block and edge coverage of a replayed corpus with a per-function report
+/// Executed translation blocks and the transitions between them are recorded by the block hooks,
+/// the blocks and branches of each function of the symbol map are decoded from the on-chip
+/// bootloader (ARM mode). A translation block runs up to the first branch, so it covers every
+/// block from its start to the next branch, edges are attributed to the branch ending a block
+
+use crate::coverage::coverage_allow_ranges;
+use crate::symbols::SymbolMap;
+use crate::yaml_conf::YAMLConfig;
+
+use libafl_qemu::GuestAddr;
+
+use log;
+use std::collections::{
+    BTreeSet,
+    HashMap,
+};
+
+/* QEMU ends translation blocks after this many instructions */
+const MAX_TB_INSNS: usize = 512;
+
+/* PCs of the instrumented blocks, indexed by their hook id */
+static mut COVERAGE_BLOCK_PCS: Vec<GuestAddr> = vec![];
+/* Executions of each block and of each transition (previous block, block) */
+static mut COVERAGE_BLOCKS: Option<HashMap<GuestAddr, u64>> = None;
+static mut COVERAGE_EDGES: Option<HashMap<(GuestAddr, GuestAddr), u64>> = None;
+static mut COVERAGE_PREV: Option<GuestAddr> = None;
+
+/* Branch instruction: (conditional, direct target) */
+type Branch = (bool, Option<GuestAddr>);
+
+/* Block and edge coverage of one function */
+pub struct FunctionCoverage {
+    pub name:       String,
+    pub begin:      GuestAddr,
+    pub end:        GuestAddr,
+    // (block, hits)
+    pub blocks:     Vec<(GuestAddr, u64)>,
+    // (branch, target, taken, hits)
+    pub edges:      Vec<(GuestAddr, GuestAddr, bool, u64)>,
+    pub target:     bool,
+}
+
+/// Records all blocks from now on, has to be called before the hooks are generated
+pub fn coverage_enable() {
+    unsafe {
+        COVERAGE_BLOCKS = Some(HashMap::new());
+        COVERAGE_EDGES = Some(HashMap::new());
+    }
+}
+
+pub fn coverage_enabled() -> bool {
+    unsafe { COVERAGE_BLOCKS.is_some() }
+}
+
+/// Has to be called before each execution
+pub fn coverage_reset() {
+    unsafe { COVERAGE_PREV = None; }
+}
+
+/// Registers a block instrumented by the block hooks
+pub fn coverage_gen_block(id: u64, pc: GuestAddr) {
+    unsafe {
+        if COVERAGE_BLOCK_PCS.len() <= id as usize {
+            COVERAGE_BLOCK_PCS.resize(id as usize + 1, 0);
+        }
+        COVERAGE_BLOCK_PCS[id as usize] = pc;
+    }
+}
+
+pub fn coverage_exec_block(id: u64) {
+    unsafe {
+        let pc = match COVERAGE_BLOCK_PCS.get(id as usize) {
+            Some(pc) => *pc,
+            None => return,
+        };
+        if let Some(blocks) = COVERAGE_BLOCKS.as_mut() {
+            *blocks.entry(pc).or_insert(0) += 1;
+        }
+        if let (Some(prev), Some(edges)) = (COVERAGE_PREV, COVERAGE_EDGES.as_mut()) {
+            *edges.entry((prev, pc)).or_insert(0) += 1;
+        }
+        COVERAGE_PREV = Some(pc);
+    }
+}
+
+/* ARM mode instructions writing the PC */
+fn decode_branch(insn: u32, addr: GuestAddr) -> Option<Branch> {
+    let cond = insn >> 28;
+    if cond == 0xf {
+        // BLX <label>, unconditional encoding with the H bit
+        if (insn & 0xfe00_0000) == 0xfa00_0000 {
+            let offset = (((insn << 8) as i32) >> 6) as u32 | ((insn >> 23) & 0x2);
+            return Some((false, Some(addr.wrapping_add(8).wrapping_add(offset))));
+        }
+        return None;
+    }
+    let conditional = cond != 0xe;
+    if (insn & 0x0e00_0000) == 0x0a00_0000 {
+        // B/BL <label>
+        let offset = (((insn << 8) as i32) >> 6) as u32;
+        Some((conditional, Some(addr.wrapping_add(8).wrapping_add(offset))))
+    } else if (insn & 0x0fff_ffd0) == 0x012f_ff10 {
+        // BX/BLX <Rm>
+        Some((conditional, None))
+    } else if (insn & 0x0e10_8000) == 0x0810_8000 {
+        // LDM with PC in the list (pop {..., pc})
+        Some((conditional, None))
+    } else if (insn & 0x0c10_f000) == 0x0410_f000 {
+        // LDR PC
+        Some((conditional, None))
+    } else if (insn & 0x0c00_f000) == 0x0000_f000 && (insn & 0x0190_0000) != 0x0100_0000 && (insn & 0x0200_0090) != 0x0000_0090 {
+        // Data processing with PC as destination (mov pc, lr)
+        Some((conditional, None))
+    } else {
+        None
+    }
+}
+
+fn read_insn(bin: &[u8], base: GuestAddr, addr: GuestAddr) -> Option<u32> {
+    let offset = addr.checked_sub(base)? as usize;
+    let bytes = bin.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/* Branch ending the translation block starting at pc */
+fn block_end(bin: &[u8], base: GuestAddr, pc: GuestAddr) -> Option<GuestAddr> {
+    for i in 0..MAX_TB_INSNS {
+        let addr = pc + 4 * i as GuestAddr;
+        if decode_branch(read_insn(bin, base, addr)?, addr).is_some() {
+            return Some(addr);
+        }
+    }
+    None
+}
+
+fn function_coverage(bin: &[u8], base: GuestAddr, name: &str, begin: GuestAddr, end: GuestAddr,
+    tb_hits: &HashMap<GuestAddr, u64>, edge_hits: &HashMap<(GuestAddr, GuestAddr), u64>) -> FunctionCoverage {
+    // Branches and the leaders of the blocks
+    let mut branches: Vec<(GuestAddr, Branch)> = vec![];
+    let mut leaders = BTreeSet::new();
+    leaders.insert(begin);
+    let mut addr = begin;
+    while addr < end {
+        let insn = match read_insn(bin, base, addr) {
+            Some(insn) => insn,
+            None => break,
+        };
+        if let Some(branch) = decode_branch(insn, addr) {
+            branches.push((addr, branch));
+            if addr + 4 < end {
+                leaders.insert(addr + 4);
+            }
+            if let Some(target) = branch.1 {
+                if begin <= target && target < end {
+                    leaders.insert(target);
+                }
+            }
+        }
+        addr += 4;
+    }
+    let leaders: Vec<GuestAddr> = leaders.into_iter().collect();
+
+    // A translation block covers the blocks up to its first branch
+    let mut blocks: Vec<(GuestAddr, u64)> = leaders.iter().map(|leader| (*leader, 0)).collect();
+    for (pc, hits) in tb_hits.iter() {
+        if *pc < begin || *pc >= end {
+            continue;
+        }
+        let last = block_end(bin, base, *pc).unwrap_or(end - 4);
+        let first = leaders.partition_point(|leader| leader <= pc) - 1;
+        for block in blocks[first..].iter_mut() {
+            if block.0 > last {
+                break;
+            }
+            block.1 += hits;
+        }
+    }
+
+    // Edges taken from a branch are transitions from a translation block ending at it
+    let mut branch_hits: HashMap<(GuestAddr, GuestAddr), u64> = HashMap::new();
+    for ((prev, pc), hits) in edge_hits.iter() {
+        if *prev < begin || *prev >= end {
+            continue;
+        }
+        if let Some(branch) = block_end(bin, base, *prev) {
+            *branch_hits.entry((branch, *pc)).or_insert(0) += hits;
+        }
+    }
+    let mut edges = vec![];
+    for (addr, (conditional, target)) in branches.iter() {
+        if let Some(target) = target {
+            edges.push((*addr, *target, true, *branch_hits.get(&(*addr, *target)).unwrap_or(&0)));
+        }
+        if *conditional {
+            edges.push((*addr, addr + 4, false, *branch_hits.get(&(*addr, addr + 4)).unwrap_or(&0)));
+        }
+    }
+
+    FunctionCoverage {
+        name:   name.to_string(),
+        begin:  begin,
+        end:    end,
+        blocks: blocks,
+        edges:  edges,
+        target: false,
+    }
+}
+
+/// Coverage of each function of the symbol map inside the on-chip bootloader, functions in
+/// "coverage: allow:" are the targets, every entered function without an allow list
+pub fn coverage_functions(conf: &YAMLConfig, symbols: &SymbolMap, bin: &[u8], base: GuestAddr) -> Vec<FunctionCoverage> {
+    let empty = HashMap::new();
+    let tb_hits = unsafe { COVERAGE_BLOCKS.as_ref().unwrap_or(&empty) };
+    let empty = HashMap::new();
+    let edge_hits = unsafe { COVERAGE_EDGES.as_ref().unwrap_or(&empty) };
+    let bin_end = base + bin.len() as GuestAddr;
+    let allow = coverage_allow_ranges(conf, symbols);
+    let mut functions = vec![];
+    for (begin, end, name) in symbols.symbols.iter() {
+        if *begin < base || *begin >= bin_end {
+            log::debug!("Skipping {} outside of the on-chip bootloader", name);
+            continue;
+        }
+        let mut function = function_coverage(bin, base, name, *begin, (*end).min(bin_end), tb_hits, edge_hits);
+        function.target = if allow.is_empty() {
+            function.blocks.iter().any(|block| block.1 != 0)
+        } else {
+            allow.iter().any(|range| range.0 < function.end && function.begin < range.1)
+        };
+        functions.push(function);
+    }
+    functions
+}
+
+fn percent(covered: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 100.0;
+    }
+    (1000.0 * covered as f64 / total as f64).round() / 10.0
+}
+
+fn json_str(input: &str) -> String {
+    format!("\"{}\"", input.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// JSON report with block/edge coverage per function and the uncovered branches of the targets
+pub fn coverage_json(functions: &[FunctionCoverage], num_inputs: usize) -> String {
+    let mut out_str = format!("{{\n  \"inputs\": {},\n  \"functions\": [", num_inputs);
+    for (i, function) in functions.iter().enumerate() {
+        let blocks_covered = function.blocks.iter().filter(|block| block.1 != 0).count();
+        let edges_covered = function.edges.iter().filter(|edge| edge.3 != 0).count();
+        out_str.push_str(if i == 0 { "\n" } else { ",\n" });
+        out_str.push_str(&format!("    {{\n      \"name\": {},\n", json_str(&function.name)));
+        out_str.push_str(&format!("      \"begin\": \"{:#010x}\",\n      \"end\": \"{:#010x}\",\n", function.begin, function.end));
+        out_str.push_str(&format!("      \"target\": {},\n", function.target));
+        out_str.push_str(&format!("      \"blocks\": {},\n      \"blocks_covered\": {},\n      \"block_coverage\": {:.1},\n",
+            function.blocks.len(), blocks_covered, percent(blocks_covered, function.blocks.len())));
+        out_str.push_str(&format!("      \"edges\": {},\n      \"edges_covered\": {},\n      \"edge_coverage\": {:.1},\n",
+            function.edges.len(), edges_covered, percent(edges_covered, function.edges.len())));
+        out_str.push_str("      \"uncovered_branches\": [");
+        if function.target {
+            let uncovered: Vec<String> = function.edges.iter()
+                .filter(|edge| edge.3 == 0)
+                .map(|edge| format!("\n        {{ \"addr\": \"{:#010x}\", \"target\": \"{:#010x}\", \"taken\": {} }}", edge.0, edge.1, edge.2))
+                .collect();
+            out_str.push_str(&uncovered.join(","));
+            if !uncovered.is_empty() {
+                out_str.push_str("\n      ");
+            }
+        }
+        out_str.push_str("]\n    }");
+    }
+    out_str.push_str("\n  ]\n}\n");
+    out_str
+}
+
+/// lcov-like report, blocks as lines and the edges of each branch as its branches,
+/// addresses instead of line numbers
+pub fn coverage_lcov(functions: &[FunctionCoverage], module: &str) -> String {
+    let mut out_str = format!("TN:aspfuzz\nSF:{}\n", module);
+    for function in functions.iter() {
+        out_str.push_str(&format!("FN:{:#010x},{}\n", function.begin, function.name));
+    }
+    for function in functions.iter() {
+        out_str.push_str(&format!("FNDA:{},{}\n", function.blocks.first().map_or(0, |block| block.1), function.name));
+    }
+    out_str.push_str(&format!("FNF:{}\n", functions.len()));
+    out_str.push_str(&format!("FNH:{}\n", functions.iter().filter(|function| function.blocks.iter().any(|block| block.1 != 0)).count()));
+    let mut num_edges = 0;
+    let mut num_edges_hit = 0;
+    for function in functions.iter() {
+        for edge in function.edges.iter() {
+            let hits = if edge.3 == 0 { "-".to_string() } else { edge.3.to_string() };
+            out_str.push_str(&format!("BRDA:{:#010x},0,{},{}\n", edge.0, if edge.2 { 0 } else { 1 }, hits));
+        }
+        num_edges += function.edges.len();
+        num_edges_hit += function.edges.iter().filter(|edge| edge.3 != 0).count();
+    }
+    out_str.push_str(&format!("BRF:{}\nBRH:{}\n", num_edges, num_edges_hit));
+    let mut num_blocks = 0;
+    let mut num_blocks_hit = 0;
+    for function in functions.iter() {
+        for block in function.blocks.iter() {
+            out_str.push_str(&format!("DA:{:#010x},{}\n", block.0, block.1));
+        }
+        num_blocks += function.blocks.len();
+        num_blocks_hit += function.blocks.iter().filter(|block| block.1 != 0).count();
+    }
+    out_str.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", num_blocks, num_blocks_hit));
+    out_str
+}
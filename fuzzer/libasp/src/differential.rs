@@ -0,0 +1,266 @@
+/// Differential runs of the same logical input against several Zen generations
+/// Inputs are translated between configs by the role of their regions (FET, n-th directory,
+/// entry header) in the base images, the verdicts of each generation are compared afterwards
+
+use crate::amd_firmware::FlashLayout;
+use crate::initial_inputs::{
+    region_role,
+    RegionRole,
+};
+use crate::yaml_conf::YAMLConfig;
+
+use libafl_qemu::GuestAddr;
+use log;
+use std::collections::HashMap;
+use std::fs;
+use std::str::FromStr;
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+/* How an execution ended, sinks are compared by their index in "harness: sinks:" */
+#[derive(Copy, Clone, PartialEq)]
+pub enum DiffVerdict {
+    Sink(usize),
+    Crash,
+    Hang,
+    Smash,
+    Exception,
+}
+
+/// Files of corpus directories or single inputs, without metadata and lock files
+pub fn corpus_files(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut inputs = vec![];
+    for path in paths.iter() {
+        let mut files: Vec<PathBuf> = if path.is_dir() {
+            fs::read_dir(path).unwrap()
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .collect()
+        } else {
+            vec![path.clone()]
+        };
+        files.sort();
+        for file in files.into_iter() {
+            let hidden = file.file_name().map_or(true, |name| name.to_string_lossy().starts_with('.'));
+            if file.is_file() && !hidden {
+                inputs.push(file);
+            }
+        }
+    }
+    inputs
+}
+
+/// Base flash image of a config with its parsed directory tree, read once per config and
+/// shared by the translation of all inputs
+pub struct FlashBase {
+    image:  Vec<u8>,
+    layout: FlashLayout,
+}
+
+impl FlashBase {
+    pub fn new(conf: &YAMLConfig) -> Self {
+        Self::from_image(fs::read(Path::new(&conf.flash_base)).unwrap())
+    }
+
+    fn from_image(image: Vec<u8>) -> Self {
+        let layout = FlashLayout::parse(&image);
+        Self {
+            image:  image,
+            layout: layout,
+        }
+    }
+}
+
+/// Translates an input of one config to another, regions without an equivalent keep the
+/// content of the base image of the target
+pub fn translate_input(from: &YAMLConfig, from_base: &FlashBase, to: &YAMLConfig, to_base: &FlashBase, input: &[u8]) -> Vec<u8> {
+    translate_regions(from_base, &from.input_mem, to_base, &to.input_mem, input)
+}
+
+fn translate_regions(
+    from_base: &FlashBase,
+    from_mem: &[(GuestAddr, usize)],
+    to_base: &FlashBase,
+    to_mem: &[(GuestAddr, usize)],
+    input: &[u8],
+) -> Vec<u8> {
+    // Regions of the input with their role
+    let mut regions: Vec<(RegionRole, usize, &[u8])> = vec![];
+    let mut offset = 0;
+    for mem in from_mem.iter() {
+        let end = (offset + mem.1).min(input.len());
+        let bytes = if offset < end { &input[offset..end] } else { &[] };
+        regions.push((region_role(&from_base.layout, mem.0 as usize), mem.0 as usize, bytes));
+        offset += mem.1;
+    }
+
+    let mut translated = vec![];
+    for mem in to_mem.iter() {
+        let start = (mem.0 & 0x00FF_FFFF) as usize;
+        let mut section = to_base.image.get(start..).unwrap_or(&[]).iter().take(mem.1).copied().collect::<Vec<u8>>();
+        section.resize(mem.1, 0);
+        let role = region_role(&to_base.layout, mem.0 as usize);
+        let source = regions.iter().find(|region| {
+            region.0 == role && (role != RegionRole::Absolute || region.1 == mem.0 as usize)
+        });
+        match source {
+            Some(source) => {
+                let len = source.2.len().min(section.len());
+                section[..len].copy_from_slice(&source.2[..len]);
+            },
+            None => log::debug!("No input region for {:?} at {:#010x}, keeping the base image", role, mem.0),
+        }
+        translated.extend_from_slice(&section);
+    }
+    translated
+}
+
+/// Verdicts of a run, one "name verdict" line per input
+pub fn write_verdicts(path: &Path, verdicts: &[(String, DiffVerdict)]) {
+    let mut out_str = "".to_string();
+    for (name, verdict) in verdicts.iter() {
+        out_str.push_str(&format!("{}\t{:?}\n", name, verdict));
+    }
+    fs::write(path, out_str).unwrap();
+}
+
+pub fn read_verdicts(path: &Path) -> HashMap<String, DiffVerdict> {
+    let mut verdicts = HashMap::new();
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(_) => return verdicts,
+    };
+    for line in text.lines() {
+        if let Some((name, verdict)) = line.split_once('\t') {
+            if let Ok(verdict) = DiffVerdict::from_str(verdict) {
+                verdicts.insert(name.to_string(), verdict);
+            }
+        }
+    }
+    verdicts
+}
+
+/// YAML report of the inputs with different verdicts, with the number of differences
+pub fn diff_report(labels: &[String], inputs: &[String], verdicts: &[HashMap<String, DiffVerdict>]) -> (String, usize) {
+    let mut out_str = format!("# Differential run of {} inputs against {}\n", inputs.len(), labels.join(", "));
+    out_str.push_str("# Sinks are compared by their index in \"harness: sinks:\"\n");
+    out_str.push_str("differences:\n");
+    let mut num_diffs = 0;
+    for input in inputs.iter() {
+        let results: Vec<Option<DiffVerdict>> = verdicts.iter().map(|run| run.get(input).copied()).collect();
+        if results.windows(2).all(|pair| pair[0] == pair[1]) {
+            continue;
+        }
+        num_diffs += 1;
+        out_str.push_str(&format!("    - input:    \"{}\"\n", input));
+        for (label, result) in labels.iter().zip(results.iter()) {
+            let result = match result {
+                Some(verdict) => format!("{:?}", verdict),
+                None => "missing".to_string(),
+            };
+            out_str.push_str(&format!("      {:<10}\"{}\"\n", format!("{}:", label), result));
+        }
+    }
+    if num_diffs == 0 {
+        out_str.push_str("    - null\n");
+    }
+    (out_str, num_diffs)
+}
+
+impl Debug for DiffVerdict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match *self {
+            DiffVerdict::Sink(i)    => write!(f, "sink {}", i),
+            DiffVerdict::Crash      => write!(f, "crash"),
+            DiffVerdict::Hang       => write!(f, "hang"),
+            DiffVerdict::Smash      => write!(f, "smash"),
+            DiffVerdict::Exception  => write!(f, "exception"),
+        }
+    }
+}
+
+impl FromStr for DiffVerdict {
+    type Err = ();
+    fn from_str(input: &str) -> Result<DiffVerdict, ()> {
+        match input {
+            "crash"     => Ok(DiffVerdict::Crash),
+            "hang"      => Ok(DiffVerdict::Hang),
+            "smash"     => Ok(DiffVerdict::Smash),
+            "exception" => Ok(DiffVerdict::Exception),
+            _           => match input.strip_prefix("sink ") {
+                Some(i) => i.parse::<usize>().map(DiffVerdict::Sink).map_err(|_| ()),
+                None    => Err(()),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amd_firmware::{
+        DirectoryKind,
+        FET_PSP_DIR_OFFSETS,
+        FET_SIGNATURE,
+    };
+
+    /* FET at 0x20000 pointing to an empty PSP directory */
+    fn image_with_dir(dir: usize) -> Vec<u8> {
+        let mut image = vec![0xff; 0x4_0000];
+        image[0x2_0000..0x2_0004].copy_from_slice(&FET_SIGNATURE.to_le_bytes());
+        let ptr = 0x2_0000 + FET_PSP_DIR_OFFSETS[0];
+        image[ptr..ptr+4].copy_from_slice(&(dir as u32).to_le_bytes());
+        image[dir..dir+4].copy_from_slice(&DirectoryKind::Psp.magic().to_le_bytes());
+        image[dir+8..dir+12].copy_from_slice(&0u32.to_le_bytes());
+        image
+    }
+
+    #[test]
+    fn translate_by_region_role() {
+        let from_base = FlashBase::from_image(image_with_dir(0x3_0000));
+        let mut to_image = image_with_dir(0x3_4000);
+        to_image[0x20..0x24].copy_from_slice(&[0xaa; 4]);
+        let to_base = FlashBase::from_image(to_image);
+        let from_mem = [(0x3_0000, 0x10), (0x10, 4)];
+        // Directory, same absolute region, region without an equivalent
+        let to_mem = [(0x3_4000, 0x10), (0x10, 4), (0x20, 4)];
+        let input: Vec<u8> = (0..0x14).collect();
+
+        let translated = translate_regions(&from_base, &from_mem, &to_base, &to_mem, &input);
+        assert_eq!(&translated[..0x10], &input[..0x10]);
+        assert_eq!(&translated[0x10..0x14], &input[0x10..0x14]);
+        assert_eq!(&translated[0x14..], &[0xaa; 4]);
+    }
+
+    #[test]
+    fn translate_short_input() {
+        let base = FlashBase::from_image(image_with_dir(0x3_0000));
+        let mem = [(0x3_0000, 0x10)];
+        // Missing bytes keep the base image
+        let translated = translate_regions(&base, &mem, &base, &mem, &[0; 4]);
+        assert_eq!(&translated[..4], &[0; 4]);
+        assert_eq!(&translated[4..], &base.image[0x3_0004..0x3_0010]);
+    }
+
+    #[test]
+    fn verdict_round_trip() {
+        let verdicts = [DiffVerdict::Sink(0), DiffVerdict::Sink(12), DiffVerdict::Crash, DiffVerdict::Hang,
+            DiffVerdict::Smash, DiffVerdict::Exception];
+        for verdict in verdicts.iter() {
+            assert!(DiffVerdict::from_str(&format!("{:?}", verdict)) == Ok(*verdict));
+        }
+    }
+
+    #[test]
+    fn verdict_rejects_unknown() {
+        assert!(DiffVerdict::from_str("sink").is_err());
+        assert!(DiffVerdict::from_str("sink x").is_err());
+        assert!(DiffVerdict::from_str("Crash").is_err());
+        assert!(DiffVerdict::from_str("").is_err());
+    }
+}
@@ -0,0 +1,155 @@
+/// Checking writes of devices (CCP, SMN-mapped devices) into protected SRAM regions
+/// Device models of qemu-libafl-asp write SRAM without passing the CPU write hooks, with the
+/// "dma_hooks" feature they report each write to the exported aspfuzz_dma_write_hook before
+/// it is performed. Writes are checked against the protected regions of the copy functions
+/// and the "W" watchpoints, allowed accessors of both are CPU addresses and don't apply.
+/// Prerequisite of the "dma_hooks" feature, qemu-libafl-asp has to export
+///     void (*aspfuzz_dma_write_hook)(uint32_t device, uint32_t addr, uint32_t len);
+/// and call it (if set) from the CCP (device 0) and SMN (device 1) models before each SRAM write,
+/// without the export the fuzzer doesn't link
+
+use crate::watchpoints::{
+    watch_lookup,
+    WatchAccess,
+    WatchAction,
+};
+use crate::yaml_conf::borrow_global_conf;
+
+use libafl_qemu::*;
+use libafl::prelude::*;
+
+use log;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+
+/* Device ids as passed by the device models */
+#[derive(Copy, Clone, PartialEq)]
+pub enum DmaDevice {
+    Ccp,
+    Smn,
+}
+
+#[derive(Clone)]
+pub struct DmaViolation {
+    pub device: DmaDevice,
+    pub addr:   GuestAddr,
+    pub len:    u32,
+    pub region: String,
+}
+
+#[cfg(feature = "dma_hooks")]
+extern "C" {
+    static mut aspfuzz_dma_write_hook: Option<extern "C" fn(device: u32, addr: GuestAddr, len: u32)>;
+}
+
+static mut DMA_VIOLATION: Option<DmaViolation> = None;
+static mut DMA_EMULATOR: u64 = 0;
+
+/// Registers the device write hook in qemu-libafl-asp
+pub fn dma_init(emu: &Emulator) {
+    let devices = &borrow_global_conf().unwrap().crashes_dma;
+    log::info!("Checking device writes of {:?}", devices);
+    unsafe { DMA_EMULATOR = emu as *const _ as u64; }
+    #[cfg(feature = "dma_hooks")]
+    unsafe {
+        aspfuzz_dma_write_hook = Some(dma_write_hook);
+    }
+    #[cfg(not(feature = "dma_hooks"))]
+    log::warn!("Device writes aren't checked, built without the \"dma_hooks\" feature");
+}
+
+/// Has to be called before each execution
+pub fn dma_reset() {
+    unsafe { DMA_VIOLATION = None; }
+}
+
+pub fn dma_violation() -> Option<DmaViolation> {
+    unsafe { DMA_VIOLATION.clone() }
+}
+
+/* Protected region overlapping [addr, addr + len), with the action taken */
+fn protected_region(addr: GuestAddr, len: u32) -> Option<(String, WatchAction)> {
+    let conf = borrow_global_conf().unwrap();
+    let end = addr.saturating_add(len);
+    for copy_fn in conf.crashes_copy_fns.iter() {
+        for region in copy_fn.protected.iter() {
+            if addr < region.end && region.begin < end {
+                return Some((format!("{} [{:#010x}, {:#010x})", copy_fn.name, region.begin, region.end), WatchAction::Objective));
+            }
+        }
+    }
+    let i = watch_lookup(WatchAccess::Write, addr, len as usize)?;
+    let watch = &conf.crashes_watch[i];
+    Some((format!("\"{}\" [{:#010x}, {:#010x})", watch.name, watch.begin, watch.end), watch.action))
+}
+
+#[allow(dead_code)]
+extern "C" fn dma_write_hook(device: u32, addr: GuestAddr, len: u32) {
+    let device = match device {
+        0 => DmaDevice::Ccp,
+        1 => DmaDevice::Smn,
+        _ => return,
+    };
+    if len == 0 || !borrow_global_conf().unwrap().crashes_dma.contains(&device) {
+        return;
+    }
+    let (region, action) = match protected_region(addr, len) {
+        Some(region) => region,
+        None => return,
+    };
+    let violation = DmaViolation { device, addr, len, region };
+    match action {
+        WatchAction::Objective => {
+            log::debug!("Device write: {:?}", violation);
+            unsafe {
+                if DMA_VIOLATION.is_some() {
+                    return;
+                }
+                DMA_VIOLATION = Some(violation);
+                let emu = (DMA_EMULATOR as *const Emulator).as_ref().unwrap();
+                emu.current_cpu().unwrap().trigger_breakpoint();
+            }
+        },
+        WatchAction::Log => log::info!("Device write: {:?}", violation),
+        WatchAction::Ignore => (),
+    }
+}
+
+/// Metadata of a device write into a protected region
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DmaMetadata {
+    pub violation: String,
+}
+
+impl_serdeany!(DmaMetadata);
+
+impl Debug for DmaDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match *self {
+            DmaDevice::Ccp  => write!(f, "ccp"),
+            DmaDevice::Smn  => write!(f, "smn"),
+        }
+    }
+}
+
+impl FromStr for DmaDevice {
+    type Err = ();
+    fn from_str(input: &str) -> Result<DmaDevice, ()> {
+        match input {
+            "ccp"   => Ok(DmaDevice::Ccp),
+            "smn"   => Ok(DmaDevice::Smn),
+            _       => Err(()),
+        }
+    }
+}
+
+impl Debug for DmaViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        write!(f, "{:?} writes [{:#010x}, {:#010x}) into {}",
+            self.device, self.addr, self.addr.saturating_add(self.len), self.region)
+    }
+}
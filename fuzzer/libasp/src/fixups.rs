@@ -0,0 +1,212 @@
+/// Fixing up the input right before it is written to flash memory
+/// Recomputes checksums so that every test-case is a valid flash image without tunneling
+
+use libafl_qemu::GuestAddr;
+
+use log;
+use std::fs;
+use std::str::FromStr;
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+
+#[derive(Copy, Clone)]
+pub enum FixupKind {
+    /*
+     *  AMD flavour of Fletcher-32 (psptool.utils.fletcher32):
+     *  - 16-bit little endian words
+     *  - both sums start at 0xffff
+     *  - 4-byte little endian result
+     */
+    Fletcher32,
+}
+
+/*
+ *  Covered range:
+ *  [begin, begin + size + (*count * stride)]
+ *  Result is written as 4 bytes to dest
+ */
+#[derive(Clone)]
+pub struct Fixup {
+    pub kind:   FixupKind,
+    pub begin:  GuestAddr,
+    pub size:   usize,
+    pub count:  Option<GuestAddr>,
+    pub stride: usize,
+    pub dest:   GuestAddr,
+}
+
+pub struct InputFixups {
+    fixups:         Vec<Fixup>,
+    input_mem:      Vec<(GuestAddr, usize)>,
+    input_fixed:    Vec<(GuestAddr, GuestAddr)>,
+    flash_size:     usize,
+    flash_base:     Vec<u8>,
+}
+
+impl InputFixups {
+    pub fn new(
+        fixups: &Vec<Fixup>,
+        input_mem: &Vec<(GuestAddr, usize)>,
+        input_fixed: &Vec<(GuestAddr, GuestAddr)>,
+        flash_size: usize,
+        flash_base: &str,
+    ) -> Self {
+        // The base image is only needed if there is something to fix up
+        let flash_base = if fixups.is_empty() {
+            vec![]
+        } else {
+            fs::read(flash_base).expect("Unable to read flash base image for input fixups")
+        };
+        Self {
+            fixups:         fixups.clone(),
+            input_mem:      input_mem.clone(),
+            input_fixed:    input_fixed.clone(),
+            flash_size:     flash_size,
+            flash_base:     flash_base,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fixups.is_empty()
+    }
+
+    /// Returns the values to write on top of the input, in order
+    /// `input` has to be the already padded input of `input_total_size` bytes
+    pub fn apply(&self, input: &[u8]) -> Vec<(GuestAddr, [u8; 4])> {
+        let mut writes: Vec<(GuestAddr, [u8; 4])> = vec![];
+        for fixup in self.fixups.iter() {
+            let mut len = fixup.size;
+            if let Some(count_addr) = fixup.count {
+                let count = u32::from_le_bytes(self.read(input, &writes, count_addr, 4).try_into().unwrap());
+                len = len.saturating_add((count as usize).saturating_mul(fixup.stride));
+            }
+            // Never read past the end of flash memory
+            len = len.min(self.flash_size.saturating_sub(fixup.begin as usize));
+            let data = self.read(input, &writes, fixup.begin, len);
+            let checksum = match fixup.kind {
+                FixupKind::Fletcher32 => fletcher32(&data),
+            };
+            log::debug!("Fixup {:?} [{:#010x}, {:#010x}] -> {:#010x}: {:#010x}",
+                fixup.kind, fixup.begin, fixup.begin as usize + len, fixup.dest, checksum);
+            writes.push((fixup.dest, checksum.to_le_bytes()));
+        }
+        writes
+    }
+
+    /* Flash content as seen by the target: base image < input mem < fixed values < earlier fixups */
+    fn read(&self, input: &[u8], writes: &[(GuestAddr, [u8; 4])], addr: GuestAddr, len: usize) -> Vec<u8> {
+        let begin = addr as usize;
+        let end = begin + len;
+        let mut data = vec![0; len];
+        if begin < self.flash_base.len() {
+            let base_end = end.min(self.flash_base.len());
+            data[..base_end-begin].copy_from_slice(&self.flash_base[begin..base_end]);
+        }
+        let mut input = input;
+        for mem in self.input_mem.iter() {
+            overlay(&mut data, begin, mem.0 as usize, &input[..mem.1]);
+            input = &input[mem.1..];
+        }
+        for fixed in self.input_fixed.iter() {
+            overlay(&mut data, begin, fixed.0 as usize, &fixed.1.to_le_bytes());
+        }
+        for write in writes.iter() {
+            overlay(&mut data, begin, write.0 as usize, &write.1);
+        }
+        data
+    }
+}
+
+/* Copies the intersection of [src_addr, src_addr + src.len()] into data starting at data_addr */
+fn overlay(data: &mut [u8], data_addr: usize, src_addr: usize, src: &[u8]) {
+    let start = data_addr.max(src_addr);
+    let end = (data_addr + data.len()).min(src_addr + src.len());
+    if start >= end {
+        return;
+    }
+    data[start-data_addr..end-data_addr].copy_from_slice(&src[start-src_addr..end-src_addr]);
+}
+
+pub fn fletcher32(data: &[u8]) -> u32 {
+    let mut c0: u64 = 0xffff;
+    let mut c1: u64 = 0xffff;
+    // Fold every 360 words to avoid overflowing the sums
+    for block in data.chunks(2*360) {
+        for word in block.chunks_exact(2) {
+            c0 += u16::from_le_bytes([word[0], word[1]]) as u64;
+            c1 += c0;
+        }
+        c0 = (c0 & 0xffff) + (c0 >> 16);
+        c1 = (c1 & 0xffff) + (c1 >> 16);
+    }
+    c0 = (c0 & 0xffff) + (c0 >> 16);
+    c1 = (c1 & 0xffff) + (c1 >> 16);
+    ((c1 << 16) | c0) as u32
+}
+
+impl Debug for FixupKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match *self {
+            FixupKind::Fletcher32 => write!(f, "fletcher32"),
+        }
+    }
+}
+
+impl FromStr for FixupKind {
+    type Err = ();
+    fn from_str(input: &str) -> Result<FixupKind, ()> {
+        match input {
+            "fletcher32"    => Ok(FixupKind::Fletcher32),
+            _               => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input_fixups(fixups: Vec<Fixup>, input_mem: Vec<(GuestAddr, usize)>, flash_base: Vec<u8>) -> InputFixups {
+        InputFixups {
+            fixups:         fixups,
+            input_mem:      input_mem,
+            input_fixed:    vec![],
+            flash_size:     flash_base.len(),
+            flash_base:     flash_base,
+        }
+    }
+
+    #[test]
+    fn fletcher32_matches_psptool() {
+        assert_eq!(fletcher32(&[]), 0xffff_ffff);
+        assert_eq!(fletcher32(&[1, 0, 2, 0]), 0x0004_0003);
+        // Longer than one folding block
+        let data: Vec<u8> = (0..10).flat_map(|_| 0..=255u8).collect();
+        assert_eq!(fletcher32(&data), 0xfaf0_7d82);
+    }
+
+    #[test]
+    fn fixup_covers_the_input() {
+        let fixup = Fixup { kind: FixupKind::Fletcher32, begin: 0x10, size: 0x4, count: None, stride: 0, dest: 0x0 };
+        let fixups = input_fixups(vec![fixup], vec![(0x10, 4)], vec![0xff; 0x20]);
+        assert_eq!(fixups.apply(&[1, 0, 2, 0]), vec![(0x0, 0x0004_0003u32.to_le_bytes())]);
+    }
+
+    #[test]
+    fn fixup_count_extends_the_range() {
+        // Entry count at 0x8 read from the input, one 4-byte entry follows the count
+        let fixup = Fixup { kind: FixupKind::Fletcher32, begin: 0x8, size: 0x4, count: Some(0x8), stride: 0x4, dest: 0x4 };
+        let fixups = input_fixups(vec![fixup], vec![(0x8, 8)], vec![0; 0x20]);
+        let input = [1, 0, 0, 0, 2, 0, 0, 0];
+        assert_eq!(fixups.apply(&input), vec![(0x4, fletcher32(&input).to_le_bytes())]);
+    }
+
+    #[test]
+    fn fixup_is_clamped_to_flash() {
+        let fixup = Fixup { kind: FixupKind::Fletcher32, begin: 0x18, size: 0x100, count: None, stride: 0, dest: 0x0 };
+        let fixups = input_fixups(vec![fixup], vec![], vec![0; 0x20]);
+        assert_eq!(fixups.apply(&[]), vec![(0x0, fletcher32(&[0; 8]).to_le_bytes())]);
+    }
+}
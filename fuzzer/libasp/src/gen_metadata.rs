@@ -1,11 +1,33 @@
 /// Generating metadata whenever a test-case is an objective
-/// Saves all register values
+/// Saves all register values, the decoded input fields, sanitizer, watchpoint, copy function
+/// and device write violations
 
 use libafl_qemu::*;
 use libafl::prelude::*;
 
+use crate::copy_fns::{
+    copy_fn_violation,
+    CopyMetadata,
+};
+use crate::dma_writes::{
+    dma_violation,
+    DmaMetadata,
+};
+use crate::sanitizer::{
+    sanitizer_violation,
+    SanitizerMetadata,
+};
+use crate::watchpoints::{
+    watch_violation,
+    WatchMetadata,
+};
+use crate::yaml_conf::borrow_global_conf;
+
 use log;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
 
 /// A custom testcase metadata
 #[derive(Debug, Serialize, Deserialize)]
@@ -27,6 +49,7 @@ pub struct CustomMetadata {
     pub pc: String,
     pub lr: String,
     pub cpsr: String,
+    pub fields: Vec<String>,
 }
 
 impl_serdeany!(CustomMetadata);
@@ -34,7 +57,7 @@ impl_serdeany!(CustomMetadata);
 impl CustomMetadata {
     /// Creates a new [`struct@CustomMetadata`]
     #[must_use]
-    pub fn new(regs: Vec<u64>) -> Self {
+    pub fn new(regs: Vec<u64>, fields: Vec<String>) -> Self {
         Self {
             r0: format!("{:#010x}", regs[0]),
             r1: format!("{:#010x}", regs[1]),
@@ -53,6 +76,7 @@ impl CustomMetadata {
             pc: format!("{:#010x}", regs[14]),
             lr: format!("{:#010x}", regs[15]),
             cpsr: format!("{:#010x}", regs[16]),
+            fields,
         }
     }
 }
@@ -65,6 +89,7 @@ pub struct CustomMetadataFeedback {
 impl<S> Feedback<S> for CustomMetadataFeedback
 where
     S: UsesInput  + HasClientPerfMonitor,
+    S::Input: HasTargetBytes,
 {
     #[allow(clippy::wrong_self_convention)]
     fn is_interesting<EM, OT>(
@@ -90,7 +115,36 @@ where
         for r in Regs::iter() {
            regs.push(emu.read_reg(r).unwrap());
         }
-        testcase.add_metadata(CustomMetadata::new(regs));
+        // Decode input fields
+        let mut fields = vec![];
+        if let (Some(conf), Some(input)) = (borrow_global_conf(), testcase.input()) {
+            fields = conf.input_layout.decode(input.target_bytes().as_slice());
+        }
+        testcase.add_metadata(CustomMetadata::new(regs, fields));
+        // Sanitizer violation which ended the execution
+        if let Some(violation) = sanitizer_violation() {
+            testcase.add_metadata(SanitizerMetadata {
+                violation: format!("{:?}", violation),
+            });
+        }
+        // Watchpoint which ended the execution
+        if let Some(violation) = watch_violation() {
+            testcase.add_metadata(WatchMetadata {
+                violation: format!("{:?}", violation),
+            });
+        }
+        // Copy into a protected region which ended the execution
+        if let Some(violation) = copy_fn_violation() {
+            testcase.add_metadata(CopyMetadata {
+                violation: format!("{:?}", violation),
+            });
+        }
+        // Device write into a protected region which ended the execution
+        if let Some(violation) = dma_violation() {
+            testcase.add_metadata(DmaMetadata {
+                violation: format!("{:?}", violation),
+            });
+        }
         Ok(())
     }
 }
@@ -111,3 +165,31 @@ impl CustomMetadataFeedback {
         }
     }
 }
+
+/// Addresses saved as "key": "0x..." in the metadata files of an objective directory,
+/// used to deduplicate against the objectives of a resumed campaign
+pub fn saved_addrs(dir: &Path, key: &str) -> HashSet<GuestAddr> {
+    let mut addrs = HashSet::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return addrs,
+    };
+    let prefix = format!("\"{}\": \"0x", key);
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with('.') || !name.ends_with(".metadata") {
+            continue;
+        }
+        let metadata = fs::read_to_string(entry.path()).unwrap_or_default();
+        for line in metadata.lines() {
+            let addr = line.trim()
+                .strip_prefix(&prefix)
+                .and_then(|addr| addr.split('"').next())
+                .and_then(|addr| GuestAddr::from_str_radix(addr, 16).ok());
+            if let Some(addr) = addr {
+                addrs.insert(addr);
+            }
+        }
+    }
+    addrs
+}
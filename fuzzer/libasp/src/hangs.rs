@@ -0,0 +1,236 @@
+/// Detecting hangs and saving them to their own corpus
+/// Hangs are deduplicated by the PC the execution was stuck at
+/// Infinite loops are detected by repeated block entry states and WFI/WFE with masked IRQs
+
+use libafl_qemu::*;
+use libafl::prelude::*;
+use libafl::corpus::ondisk::OnDiskMetadataFormat;
+
+use crate::gen_metadata::saved_addrs;
+use crate::yaml_conf::borrow_global_conf;
+
+use log;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+use std::path::PathBuf;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum HangReason {
+    // Wall-clock timeout of the executor
+    Timeout,
+    BlockBudget,
+    // Same block entered with the same registers too often
+    Loop,
+    // WFI/WFE with IRQs masked never returns
+    WaitMasked,
+}
+
+/* Direct-mapped table of block entry states: (hash, count, generation) */
+const LOOP_TABLE_SIZE: usize = 0x1000;
+static mut LOOP_TABLE: [(u64, u64, u64); LOOP_TABLE_SIZE] = [(0, 0, 0); LOOP_TABLE_SIZE];
+/* Entries of older executions are stale */
+static mut LOOP_GENERATION: u64 = 0;
+
+/* Multiplier of the FxHash mix of the registers, runs on every executed block */
+const LOOP_HASH_SEED: u64 = 0x517c_c1b7_2722_0a95;
+
+/* CPSR IRQ mask bit */
+const CPSR_I: u32 = 1 << 7;
+
+/* Reason of the hang in the current execution, none if it wasn't ended early */
+static mut HANG_REASON: Option<HangReason> = None;
+static mut HANG_BLOCKS: u64 = 0;
+
+/// Has to be called before each execution
+pub fn hang_reset() {
+    unsafe {
+        HANG_REASON = None;
+        HANG_BLOCKS = 0;
+        LOOP_GENERATION += 1;
+    }
+}
+
+pub fn hang_detected(reason: HangReason) {
+    unsafe { HANG_REASON = Some(reason); }
+}
+
+pub fn hang_reason() -> Option<HangReason> {
+    unsafe { HANG_REASON }
+}
+
+/// Counts an executed block, returns the number of blocks of this execution
+pub fn hang_count_block() -> u64 {
+    unsafe {
+        HANG_BLOCKS += 1;
+        HANG_BLOCKS
+    }
+}
+
+/// Whether the current block was entered with the same registers at least `threshold` times
+pub fn hang_check_loop(emu: &Emulator, threshold: u64) -> bool {
+    let mut hash: u64 = 0;
+    for reg in (Regs::R0 as i32)..=(Regs::R15 as i32) {
+        let value: u32 = emu.read_reg(reg).unwrap();
+        hash = (hash.rotate_left(5) ^ value as u64).wrapping_mul(LOOP_HASH_SEED);
+    }
+    let cpsr: u32 = emu.read_reg(Regs::Cpsr).unwrap();
+    hash = (hash.rotate_left(5) ^ cpsr as u64).wrapping_mul(LOOP_HASH_SEED);
+
+    let entry = unsafe { &mut LOOP_TABLE[hash as usize % LOOP_TABLE_SIZE] };
+    let generation = unsafe { LOOP_GENERATION };
+    if entry.0 != hash || entry.2 != generation {
+        *entry = (hash, 1, generation);
+        return false;
+    }
+    entry.1 += 1;
+    entry.1 >= threshold
+}
+
+/* WFI/WFE (ARM mode) */
+pub fn find_wait_sites(bin: &[u8], base: GuestAddr) -> Vec<GuestAddr> {
+    bin.chunks_exact(4)
+        .enumerate()
+        .filter(|(_, insn)| {
+            let insn = u32::from_le_bytes((*insn).try_into().unwrap());
+            (insn & 0x0fff_fffe) == 0x0320_f002
+        })
+        .map(|(i, _)| base + (i * 4) as GuestAddr)
+        .collect()
+}
+
+pub fn add_wait_masked_hooks(bin: &[u8], base: GuestAddr, emu: &Emulator) {
+    let sites = find_wait_sites(bin, base);
+    log::info!("Adding {} WFI/WFE hooks", sites.len());
+    for site in sites.iter() {
+        emu.set_hook(*site, wait_masked_hook, emu as *const _ as u64, false);
+    }
+}
+
+extern "C" fn wait_masked_hook(pc: GuestAddr, data: u64) {
+    let emu = unsafe { (data as *const Emulator).as_ref().unwrap() };
+    let cpsr: u32 = emu.read_reg(Regs::Cpsr).unwrap();
+    if (cpsr & CPSR_I) != 0 {
+        log::debug!("WFI/WFE with masked IRQs at {:#x}", pc);
+        hang_detected(HangReason::WaitMasked);
+        emu.current_cpu().unwrap().trigger_breakpoint();
+    }
+}
+
+/// Metadata of a hang
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HangMetadata {
+    pub reason: String,
+    pub pc: String,
+    pub lr: String,
+    pub blocks: u64,
+    pub fields: Vec<String>,
+}
+
+impl_serdeany!(HangMetadata);
+
+/// Saves timeouts with a new stuck PC to the hangs corpus, never interesting otherwise
+pub struct HangFeedback<I>
+where
+    I: Input,
+{
+    emulator: u64,
+    hangs: OnDiskCorpus<I>,
+    pcs: HashSet<GuestAddr>,
+}
+
+impl<S> Feedback<S> for HangFeedback<S::Input>
+where
+    S: UsesInput + HasClientPerfMonitor,
+    S::Input: HasTargetBytes,
+{
+    #[allow(clippy::wrong_self_convention)]
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        input: &S::Input,
+        _observers: &OT,
+        exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer,
+        OT: ObserversTuple<S>,
+    {
+        if *exit_kind != ExitKind::Timeout {
+            return Ok(false);
+        }
+        let emu = unsafe { (self.emulator as *const Emulator).as_ref().unwrap() };
+        let pc: GuestAddr = emu.read_reg(Regs::Pc).unwrap();
+        let lr: GuestAddr = emu.read_reg(Regs::Lr).unwrap();
+        let reason = hang_reason().unwrap_or(HangReason::Timeout);
+        if !self.pcs.insert(pc) {
+            log::debug!("Known hang at {:#x}", pc);
+            return Ok(false);
+        }
+        log::info!("New hang ({:?}) at {:#x}", reason, pc);
+
+        let mut fields = vec![];
+        if let Some(conf) = borrow_global_conf() {
+            fields = conf.input_layout.decode(input.target_bytes().as_slice());
+        }
+        let mut testcase = Testcase::new(input.clone());
+        testcase.add_metadata(HangMetadata {
+            reason: format!("{:?}", reason),
+            pc:     format!("{:#010x}", pc),
+            lr:     format!("{:#010x}", lr),
+            blocks: unsafe { HANG_BLOCKS },
+            fields: fields,
+        });
+        self.hangs.add(testcase)?;
+        Ok(false)
+    }
+}
+
+impl<I> Named for HangFeedback<I>
+where
+    I: Input,
+{
+    #[inline]
+    fn name(&self) -> &str {
+        "HangFeedback"
+    }
+}
+
+impl<I> HangFeedback<I>
+where
+    I: Input,
+{
+    /// Creates a new [`HangFeedback`] saving hangs to `hangs_dir`
+    #[must_use]
+    pub fn new(emulator: u64, hangs_dir: PathBuf) -> Self {
+        Self {
+            emulator,
+            pcs: saved_addrs(&hangs_dir, "pc"),
+            hangs: OnDiskCorpus::new_save_meta(hangs_dir, Some(OnDiskMetadataFormat::JsonPretty)).unwrap(),
+        }
+    }
+}
+
+impl<I> Debug for HangFeedback<I>
+where
+    I: Input,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        write!(f, "HangFeedback({} hangs)", self.pcs.len())
+    }
+}
+
+impl Debug for HangReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match *self {
+            HangReason::Timeout     => write!(f, "timeout"),
+            HangReason::BlockBudget => write!(f, "block budget"),
+            HangReason::Loop        => write!(f, "loop"),
+            HangReason::WaitMasked  => write!(f, "wait with masked IRQs"),
+        }
+    }
+}
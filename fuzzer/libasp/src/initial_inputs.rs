@@ -1,12 +1,28 @@
 /// Generate initial inputs for the fuzzer based on provided UEFI images
+/// Input regions are relocated to the equivalent structures of each image, the entry addresses
+/// of relocated directories are rewritten to where their targets end up in the input
+
+use crate::amd_firmware::*;
 
 use libafl_qemu::GuestAddr;
+use log;
 use std::fs;
 use std::path::{
     Path,
     PathBuf,
 };
 
+/* What an input region covers in the base image */
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum RegionRole {
+    Fet,
+    // n-th directory of this kind
+    Directory(DirectoryKind, usize),
+    EntryHeader,
+    // Same offset in every image
+    Absolute,
+}
+
 pub struct InitialInput {}
 
 impl InitialInput {
@@ -21,23 +37,69 @@ impl InitialInput {
         flash_size: GuestAddr,
         input_total_size: usize,
         input_dir: PathBuf,
+        base_image: &str,
     ) -> PathBuf {
-        if flash_base.is_empty() {
+        let images = collect_images(flash_base);
+        if images.is_empty() {
             let mut new_input_path = PathBuf::from(&input_dir);
             new_input_path.push("input0000");
             fs::write(new_input_path, vec![0; input_total_size]).unwrap();
         }
-        for (i, base) in flash_base.iter().enumerate() {
+
+        // Identify the input regions in the base image
+        let base_layout = FlashLayout::parse(&fs::read(Path::new(base_image)).unwrap());
+        let roles: Vec<RegionRole> = input_mem.iter().map(|mem| region_role(&base_layout, mem.0 as usize)).collect();
+        for (mem, role) in input_mem.iter().zip(roles.iter()) {
+            log::debug!("Input region {:#010x}: {:?}", mem.0, role);
+        }
+
+        for (i, base) in images.iter().enumerate() {
             let mut new_input_image = Vec::<u8>::new();
-            let image: Vec<u8> = fs::read(Path::new(base)).unwrap();
-            for mem in input_mem.iter() {
+            let image: Vec<u8> = fs::read(base).unwrap();
+            let layout = FlashLayout::parse(&image);
+
+            // Region of the image copied to each input region: (image offset, flash offset, size)
+            let mut regions = vec![];
+            for (mem, role) in input_mem.iter().zip(roles.iter()) {
                 assert!(mem.0 < flash_size && (mem.1 as GuestAddr) < flash_size, "Memory region outsize of flash memory size");
-                let mem_section = &image[((mem.0 & 0x00FF_FFFF) as usize)..((mem.0 & 0x00FF_FFFF) as usize)+mem.1];
-                new_input_image.extend_from_slice(mem_section);
+                let absolute = (mem.0 & 0x00FF_FFFF) as usize;
+                let start = match locate(&layout, role) {
+                    Some(start) => start,
+                    None => {
+                        if *role != RegionRole::Absolute {
+                            log::warn!("{:?} not found in {:?}, using {:#010x}", role, base, absolute);
+                        }
+                        absolute
+                    },
+                };
+                if start != absolute {
+                    log::info!("{:?}: relocating {:#010x} to {:#010x}", base, absolute, start);
+                }
+                regions.push((start, absolute, mem.1));
             }
-            if input_total_size != new_input_image.len() {
-                panic!("Extracted input to short");
+
+            for (region, role) in regions.iter().zip(roles.iter()) {
+                let (start, absolute, size) = *region;
+                // Pad regions which exceed the image
+                let mut mem_section = image.get(start..).unwrap_or(&[]).iter().take(size).copied().collect::<Vec<u8>>();
+                if mem_section.len() < size {
+                    log::warn!("{:?}: padding {:#010x} from {:#x} to {:#x} bytes", base, start, mem_section.len(), size);
+                }
+                mem_section.resize(size, 0);
+                if let RegionRole::Directory(..) = role {
+                    if let Some(dir) = layout.directories.iter().find(|dir| dir.offset == start) {
+                        if dir.size() > size {
+                            log::warn!("{:?}: truncating the {:?} directory at {:#010x} from {:#x} to {:#x} bytes",
+                                base, dir.kind, start, dir.size(), size);
+                        }
+                        if start != absolute {
+                            relocate_entries(&mut mem_section, dir, start, absolute, &regions);
+                        }
+                    }
+                }
+                new_input_image.extend_from_slice(&mem_section);
             }
+            assert_eq!(input_total_size, new_input_image.len(), "Extracted input has the wrong size");
             let mut new_input_path = PathBuf::from(&input_dir);
             new_input_path.push(format!("input{:#04}", i));
             fs::write(new_input_path, new_input_image).unwrap();
@@ -46,3 +108,156 @@ impl InitialInput {
     }
 }
 
+/*
+ *  Rewrites the entry addresses of a directory copied from start to absolute:
+ *  - targets inside an input region point to where that region is copied to
+ *  - other targets keep their distance to the directory
+ *  Physical addresses and flash offsets (modes 0, 1, 3) are shifted, directory-relative
+ *  addresses (mode 2) are relative to the new directory offset
+ */
+fn relocate_entries(section: &mut [u8], dir: &Directory, start: usize, absolute: usize, regions: &[(usize, usize, usize)]) {
+    for entry in dir.entries.iter() {
+        let target = match entry.addr {
+            Some(target) => target,
+            None => continue,
+        };
+        let field = entry.addr_field() - start;
+        if field + 8 > section.len() {
+            continue;
+        }
+        let new_target = regions.iter()
+            .find(|(other_start, _, other_size)| (*other_start..other_start + other_size).contains(&target))
+            .map(|(other_start, other_absolute, _)| other_absolute + (target - other_start))
+            .unwrap_or((target + absolute).wrapping_sub(start));
+        let raw = entry.addr_raw;
+        let addr = match raw >> 62 {
+            2 => new_target.wrapping_sub(absolute) as u32,
+            _ => (raw as u32).wrapping_add(new_target.wrapping_sub(target) as u32),
+        };
+        let new_raw = (raw & !0xffff_ffff) | addr as u64;
+        if new_raw != raw {
+            log::debug!("Entry {:#x} at {:#010x}: {:#018x} to {:#018x}", entry.kind, entry.offset, raw, new_raw);
+        }
+        section[field..field + 8].copy_from_slice(&new_raw.to_le_bytes());
+    }
+}
+
+/* Initial inputs are either images or directories of images */
+fn collect_images(flash_base: &Vec<String>) -> Vec<PathBuf> {
+    let mut images = vec![];
+    for base in flash_base.iter() {
+        let path = PathBuf::from(base);
+        if path.is_dir() {
+            let mut dir_images: Vec<PathBuf> = fs::read_dir(&path).unwrap()
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|entry| entry.is_file())
+                .collect();
+            dir_images.sort();
+            images.extend(dir_images);
+        } else {
+            images.push(path);
+        }
+    }
+    images
+}
+
+pub(crate) fn region_role(layout: &FlashLayout, addr: usize) -> RegionRole {
+    if layout.fet == Some(addr) {
+        return RegionRole::Fet;
+    }
+    for dir in layout.directories.iter() {
+        if dir.offset == addr {
+            let n = layout.directories.iter()
+                .take_while(|other| other.offset != addr)
+                .filter(|other| other.kind == dir.kind)
+                .count();
+            return RegionRole::Directory(dir.kind, n);
+        }
+    }
+    if layout.psp_entry(PSP_ENTRY_BOOTLOADER).and_then(|entry| entry.addr) == Some(addr) {
+        return RegionRole::EntryHeader;
+    }
+    RegionRole::Absolute
+}
+
+pub(crate) fn locate(layout: &FlashLayout, role: &RegionRole) -> Option<usize> {
+    match *role {
+        RegionRole::Fet => layout.fet,
+        RegionRole::Directory(kind, n) => layout.directories.iter()
+            .filter(|dir| dir.kind == kind)
+            .nth(n)
+            .map(|dir| dir.offset),
+        RegionRole::EntryHeader => layout.psp_entry(PSP_ENTRY_BOOTLOADER).and_then(|entry| entry.addr),
+        RegionRole::Absolute => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* PSP directory at 0x30000 with entries of (type, raw address, resolved address) */
+    fn directory(entries: &[(u32, u64, usize)]) -> (Directory, Vec<u8>) {
+        let kind = DirectoryKind::Psp;
+        let mut section = vec![0; kind.header_size() + entries.len() * kind.entry_size()];
+        let mut dir_entries = vec![];
+        for (i, (entry_kind, addr_raw, addr)) in entries.iter().enumerate() {
+            let offset = kind.header_size() + i * kind.entry_size();
+            section[offset + DirectoryEntry::ADDR_OFFSET..offset + 16].copy_from_slice(&addr_raw.to_le_bytes());
+            dir_entries.push(DirectoryEntry {
+                offset:     0x3_0000 + offset,
+                kind:       *entry_kind,
+                size:       0x100,
+                addr_raw:   *addr_raw,
+                addr:       Some(*addr),
+            });
+        }
+        let dir = Directory {
+            kind:           kind,
+            offset:         0x3_0000,
+            checksum:       0,
+            num_entries:    entries.len() as u32,
+            entries:        dir_entries,
+        };
+        (dir, section)
+    }
+
+    fn entry_addr(section: &[u8], i: usize) -> u64 {
+        let offset = 0x10 + i * 0x10 + DirectoryEntry::ADDR_OFFSET;
+        u64::from_le_bytes(section[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn relocated_entries_follow_their_regions() {
+        // Directory moves from 0x30000 to 0x20000, the entry header from 0x31000 to 0x25000
+        let (dir, mut section) = directory(&[
+            (PSP_ENTRY_BOOTLOADER, (2 << 62) | 0x1000, 0x3_1000),
+            (PSP_ENTRY_AMD_PUBKEY, (1 << 62) | 0x3_1000, 0x3_1000),
+        ]);
+        let regions = [(0x3_0000, 0x2_0000, 0x30), (0x3_1000, 0x2_5000, 0x100)];
+        relocate_entries(&mut section, &dir, 0x3_0000, 0x2_0000, &regions);
+        assert_eq!(entry_addr(&section, 0), (2 << 62) | 0x5000);
+        assert_eq!(entry_addr(&section, 1), (1 << 62) | 0x2_5000);
+    }
+
+    #[test]
+    fn relocated_entries_keep_their_distance() {
+        // Targets outside the input regions move with the directory
+        let (dir, mut section) = directory(&[
+            (PSP_ENTRY_BOOTLOADER, (2 << 62) | 0x1000, 0x3_1000),
+            (PSP_ENTRY_AMD_PUBKEY, 0xff03_8000, 0x3_8000),
+        ]);
+        let regions = [(0x3_0000, 0x2_0000, 0x30)];
+        relocate_entries(&mut section, &dir, 0x3_0000, 0x2_0000, &regions);
+        assert_eq!(entry_addr(&section, 0), (2 << 62) | 0x1000);
+        assert_eq!(entry_addr(&section, 1), 0xff02_8000);
+    }
+
+    #[test]
+    fn truncated_entries_are_skipped() {
+        let (dir, section) = directory(&[(PSP_ENTRY_AMD_PUBKEY, 0xff03_8000, 0x3_8000)]);
+        let mut truncated = section[..0x18].to_vec();
+        relocate_entries(&mut truncated, &dir, 0x3_0000, 0x2_0000, &[]);
+        assert_eq!(truncated, section[..0x18].to_vec());
+    }
+}
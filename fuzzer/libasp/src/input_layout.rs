@@ -0,0 +1,250 @@
+/// Field layout of the input regions
+/// Used to mutate structured fields and to pretty-print decoded inputs
+
+use libafl::prelude::*;
+
+use std::str::FromStr;
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+
+#[derive(Copy, Clone)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/*
+ *  A single field inside the concatenated input:
+ *  - offset:       offset into the whole input (not the region)
+ *  - width:        1, 2, 4 or 8 bytes are handled as integers, everything else as raw bytes
+ *  - values:       allowed values (enum)
+ *  - range:        allowed values [min, max]
+ *  - length_of:    field holds the length of another field in units of the given size
+ */
+#[derive(Clone)]
+pub struct LayoutField {
+    pub name:       String,
+    pub offset:     usize,
+    pub width:      usize,
+    pub endian:     Endian,
+    pub values:     Vec<u64>,
+    pub range:      Option<(u64, u64)>,
+    pub length_of:  Option<(String, usize)>,
+}
+
+impl LayoutField {
+    pub fn is_integer(&self) -> bool {
+        [1, 2, 4, 8].contains(&self.width)
+    }
+
+    pub fn max_value(&self) -> u64 {
+        if self.width >= 8 {
+            u64::MAX
+        } else {
+            (1u64 << (8 * self.width)) - 1
+        }
+    }
+
+    pub fn read(&self, input: &[u8]) -> u64 {
+        let mut value: u64 = 0;
+        for i in 0..self.width.min(8) {
+            let byte = *input.get(self.offset + i).unwrap_or(&0) as u64;
+            match self.endian {
+                Endian::Little  => value |= byte << (8 * i),
+                Endian::Big     => value = (value << 8) | byte,
+            }
+        }
+        value
+    }
+
+    pub fn write(&self, input: &mut Vec<u8>, value: u64) {
+        if input.len() < self.offset + self.width {
+            input.resize(self.offset + self.width, 0);
+        }
+        for i in 0..self.width.min(8) {
+            let shift = match self.endian {
+                Endian::Little  => 8 * i,
+                Endian::Big     => 8 * (self.width - 1 - i),
+            };
+            input[self.offset + i] = (value >> shift) as u8;
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct InputLayout {
+    pub fields: Vec<LayoutField>,
+}
+
+impl InputLayout {
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn field(&self, name: &str) -> Option<&LayoutField> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    /// Decodes all fields of the input into "name = value" strings
+    pub fn decode(&self, input: &[u8]) -> Vec<String> {
+        let mut decoded = vec![];
+        for field in self.fields.iter() {
+            if !field.is_integer() {
+                let end = (field.offset + field.width).min(input.len());
+                let bytes = if field.offset < end { &input[field.offset..end] } else { &[] };
+                let mut out_str = format!("{} = [", field.name);
+                for byte in bytes.iter().take(0x20) {
+                    out_str.push_str(&format!("{:02x}", byte));
+                }
+                if bytes.len() > 0x20 {
+                    out_str.push_str("..");
+                }
+                out_str.push_str("]");
+                decoded.push(out_str);
+                continue;
+            }
+            let value = field.read(input);
+            let mut out_str = format!("{} = {:#x}", field.name, value);
+            if !field.values.is_empty() && !field.values.contains(&value) {
+                out_str.push_str(" (not in enum)");
+            }
+            if let Some(range) = field.range {
+                if value < range.0 || value > range.1 {
+                    out_str.push_str(" (out of range)");
+                }
+            }
+            if let Some(length_of) = &field.length_of {
+                if let Some(target) = self.field(&length_of.0) {
+                    if value.saturating_mul(length_of.1 as u64) > target.width as u64 {
+                        out_str.push_str(&format!(" (exceeds {})", length_of.0));
+                    }
+                }
+            }
+            decoded.push(out_str);
+        }
+        decoded
+    }
+}
+
+/// Mutates single fields of the input according to the layout
+pub struct LayoutMutator {
+    layout: InputLayout,
+}
+
+impl LayoutMutator {
+    /// Creates a new [`LayoutMutator`]
+    #[must_use]
+    pub fn new(layout: InputLayout) -> Self {
+        Self {
+            layout
+        }
+    }
+
+    /* Interesting values for a field, most of them on the edge of what is allowed */
+    fn candidates(&self, field: &LayoutField) -> Vec<u64> {
+        let max = field.max_value();
+        let mut candidates = vec![0, 1, max, max >> 1, (max >> 1) + 1];
+        candidates.extend_from_slice(&field.values);
+        if let Some(range) = field.range {
+            candidates.extend_from_slice(&[
+                range.0,
+                range.1,
+                range.0.wrapping_sub(1) & max,
+                range.1.wrapping_add(1) & max,
+            ]);
+        }
+        if let Some(length_of) = &field.length_of {
+            if let Some(target) = self.layout.field(&length_of.0) {
+                let capacity = (target.width / length_of.1.max(1)) as u64;
+                candidates.extend_from_slice(&[
+                    capacity,
+                    capacity.wrapping_sub(1) & max,
+                    capacity.wrapping_add(1) & max,
+                ]);
+            }
+        }
+        candidates
+    }
+}
+
+impl<I, S> Mutator<I, S> for LayoutMutator
+where
+    I: Input + HasBytesVec,
+    S: HasRand,
+{
+    fn mutate(
+        &mut self,
+        state: &mut S,
+        input: &mut I,
+        _stage_idx: i32,
+    ) -> Result<MutationResult, Error> {
+        let fields: Vec<&LayoutField> = self.layout.fields.iter().filter(|field| field.is_integer()).collect();
+        if fields.is_empty() {
+            return Ok(MutationResult::Skipped);
+        }
+        let field = fields[state.rand_mut().below(fields.len() as u64) as usize];
+
+        // Either pick an interesting value or a random one in range
+        let value = if field.range.is_some() && state.rand_mut().below(4) == 0 {
+            let range = field.range.unwrap();
+            range.0 + state.rand_mut().below(range.1.saturating_sub(range.0).saturating_add(1).max(1))
+        } else {
+            let candidates = self.candidates(field);
+            candidates[state.rand_mut().below(candidates.len() as u64) as usize]
+        };
+        if field.read(input.bytes()) == value {
+            return Ok(MutationResult::Skipped);
+        }
+        field.write(input.bytes_mut(), value);
+        Ok(MutationResult::Mutated)
+    }
+}
+
+impl Named for LayoutMutator {
+    #[inline]
+    fn name(&self) -> &str {
+        "LayoutMutator"
+    }
+}
+
+impl Debug for Endian {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match *self {
+            Endian::Little  => write!(f, "little"),
+            Endian::Big     => write!(f, "big"),
+        }
+    }
+}
+
+impl FromStr for Endian {
+    type Err = ();
+    fn from_str(input: &str) -> Result<Endian, ()> {
+        match input {
+            "little"    => Ok(Endian::Little),
+            "big"       => Ok(Endian::Big),
+            _           => Err(()),
+        }
+    }
+}
+
+impl Debug for LayoutField {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        write!(f, "({},{:#x},{:#x},{:?}", self.name, self.offset, self.width, self.endian)?;
+        if !self.values.is_empty() {
+            write!(f, ",enum=[")?;
+            for value in self.values.iter() {
+                write!(f, "{:#x},", value)?;
+            }
+            write!(f, "]")?;
+        }
+        if let Some(range) = self.range {
+            write!(f, ",range=[{:#x},{:#x}]", range.0, range.1)?;
+        }
+        if let Some(length_of) = &self.length_of {
+            write!(f, ",length_of={}*{:#x}", length_of.0, length_of.1)?;
+        }
+        write!(f, ")")
+    }
+}
@@ -1,6 +1,38 @@
 // Linux only
 #![cfg(target_os = "linux")]
 
+// Parsing the AMD firmware directory tree of flash images
+pub mod amd_firmware;
+pub use amd_firmware::*;
+
+// Fast-path for the CCP backed signature verification
+pub mod ccp_fast_path;
+pub use ccp_fast_path::*;
+
+// CmpLog instrumentation of comparisons for the input-to-state stage
+pub mod cmplog_hooks;
+pub use cmplog_hooks::*;
+
+// Checking the destination of copy-like functions
+pub mod copy_fns;
+pub use copy_fns::*;
+
+// Coverage filtering by address ranges or symbols
+pub mod coverage;
+pub use coverage::*;
+
+// Aggregated coverage of a replayed corpus with a per-function report
+pub mod coverage_report;
+pub use coverage_report::*;
+
+// Differential runs against several Zen generations
+pub mod differential;
+pub use differential::*;
+
+// Checking device (DMA/CCP) writes into protected SRAM regions
+pub mod dma_writes;
+pub use dma_writes::*;
+
 // Catching CPU exception during the execution
 pub mod exception_handler;
 pub use exception_handler::*;
@@ -9,6 +41,18 @@ pub use exception_handler::*;
 pub mod gen_metadata;
 pub use gen_metadata::*;
 
+// Recomputing checksums on the input before writing it to flash
+pub mod fixups;
+pub use fixups::*;
+
+// Field layout of the input regions for mutating and decoding
+pub mod input_layout;
+pub use input_layout::*;
+
+// Detecting hangs and saving them to their own corpus
+pub mod hangs;
+pub use hangs::*;
+
 // Generate initial inputs from provided UEFI images
 pub mod initial_inputs;
 pub use initial_inputs::*;
@@ -17,10 +61,46 @@ pub use initial_inputs::*;
 pub mod reset_state;
 pub use reset_state::*;
 
+// Resuming a campaign from the on-disk queue and state checkpoints
+pub mod resume;
+pub use resume::*;
+
+// Shadow-memory sanitizer for SRAM buffers
+pub mod sanitizer;
+pub use sanitizer::*;
+
+// Corpus scheduling selected in the YAML config
+pub mod schedulers;
+pub use schedulers::*;
+
+// Shadow call stack for return-address integrity checking
+pub mod shadow_stack;
+pub use shadow_stack::*;
+
+// Replacing functions with stubs or Rust models
+pub mod stubs;
+pub use stubs::*;
+
+// Symbol map of the on-chip bootloader
+pub mod symbols;
+pub use symbols::*;
+
+// Token dictionary from comparisons in the on-chip bootloader
+pub mod tokens;
+pub use tokens::*;
+
+// Suggesting tunnels for comparisons which are never satisfied
+pub mod tunnel_discovery;
+pub use tunnel_discovery::*;
+
 // Tunneling comparisons by statically/dynamically setting register values
 pub mod tunneling;
 pub use tunneling::*;
 
+// Memory-access watchpoints with per-range policies
+pub mod watchpoints;
+pub use watchpoints::*;
+
 // Parsing the YAML config
 pub mod yaml_conf;
 pub use yaml_conf::*;
@@ -0,0 +1,111 @@
+/// Resuming a campaign from its run directory
+/// Each client keeps its queue on disk in queue/client_<n>/ and checkpoints its state (corpora,
+/// feedback maps, scheduler metadata, executions) to state/client_<n>.postcard periodically.
+/// Entries saved after the last checkpoint are loaded again when resuming
+
+use crate::differential::corpus_files;
+
+use log;
+use postcard;
+use serde::{
+    de::DeserializeOwned,
+    Serialize,
+};
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+/* The launcher numbers its clients in AFL_LAUNCHER_CLIENT, a single client is 0 */
+fn client_id() -> usize {
+    env::var("AFL_LAUNCHER_CLIENT").ok().and_then(|id| id.parse().ok()).unwrap_or(0)
+}
+
+/// Queue of this client
+pub fn client_queue_dir(queue_dir: &Path) -> PathBuf {
+    queue_dir.join(format!("client_{}", client_id()))
+}
+
+/// Checkpoint of this client
+pub fn checkpoint_path(state_dir: &Path) -> PathBuf {
+    state_dir.join(format!("client_{}.postcard", client_id()))
+}
+
+/// Writes the checkpoint next to the old one first, a crash while writing keeps the old one
+pub fn save_checkpoint<S: Serialize>(path: &Path, state: &S) {
+    let bytes = match postcard::to_allocvec(state) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            log::warn!("Unable to serialize the state for {:?}: {:?}", path, err);
+            return;
+        },
+    };
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, bytes).unwrap();
+    fs::rename(&tmp_path, path).unwrap();
+}
+
+pub fn load_checkpoint<S: DeserializeOwned>(path: &Path) -> Option<S> {
+    let bytes = fs::read(path).ok()?;
+    match postcard::from_bytes(&bytes) {
+        Ok(state) => {
+            log::info!("Resuming from {:?}", path);
+            Some(state)
+        },
+        Err(err) => {
+            log::warn!("Unable to deserialize the state of {:?}: {:?}", path, err);
+            None
+        },
+    }
+}
+
+/// Hands the queues of clients beyond num_clients (resumed with fewer cores) to client 0,
+/// their checkpoints are removed
+pub fn resume_clients(queue_dir: &Path, state_dir: &Path, num_clients: usize) {
+    let entries = match fs::read_dir(queue_dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let first_queue_dir = queue_dir.join("client_0");
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let id = match name.strip_prefix("client_").and_then(|id| id.parse::<usize>().ok()) {
+            Some(id) if id >= num_clients => id,
+            _ => continue,
+        };
+        fs::create_dir_all(&first_queue_dir).unwrap();
+        let files = corpus_files(&[entry.path()]);
+        for file in files.iter() {
+            let target = first_queue_dir.join(file.file_name().unwrap());
+            if !target.exists() {
+                fs::rename(file, target).unwrap();
+            }
+        }
+        fs::remove_dir_all(entry.path()).unwrap();
+        let _ = fs::remove_file(state_dir.join(format!("client_{}.postcard", id)));
+        log::info!("Queue of client {} with {} entries handed to client 0", id, files.len());
+    }
+}
+
+/// Entries of the queue which aren't part of the corpus (saved after the last checkpoint),
+/// they are removed from the queue with their lock and metadata files to be added again
+pub fn take_orphans(client_queue_dir: &Path, known_names: &HashSet<String>) -> Vec<Vec<u8>> {
+    let mut orphans = vec![];
+    for file in corpus_files(&[client_queue_dir.to_path_buf()]).iter() {
+        let name = file.file_name().unwrap().to_string_lossy().to_string();
+        if known_names.contains(&name) {
+            continue;
+        }
+        orphans.push(fs::read(file).unwrap());
+        fs::remove_file(file).unwrap();
+        let _ = fs::remove_file(client_queue_dir.join(format!(".{}.lafl_lock", name)));
+        let _ = fs::remove_file(client_queue_dir.join(format!(".{}.metadata", name)));
+    }
+    if !orphans.is_empty() {
+        log::info!("{} entries of {:?} aren't part of the checkpoint", orphans.len(), client_queue_dir);
+    }
+    orphans
+}
@@ -0,0 +1,242 @@
+/// Shadow-memory sanitizer for SRAM
+/// Keeps shadow state for a map of SRAM buffers and flags
+/// - writes crossing buffer boundaries, either by a single access or by consecutive stores of
+///   the same instruction (e.g. a copy loop running over the end of its destination)
+/// - reads of bytes never written during the execution in buffers marked as uninitialized
+
+use crate::yaml_conf::borrow_global_conf;
+
+use libafl_qemu::*;
+use libafl::prelude::*;
+
+use log;
+use serde::{Deserialize, Serialize};
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+
+#[derive(Clone)]
+pub struct SanitizerBuffer {
+    pub name:   String,
+    pub begin:  GuestAddr,
+    pub end:    GuestAddr,
+    // Content is undefined at the start of each execution
+    pub uninit: bool,
+}
+
+#[derive(Clone)]
+pub enum SanitizerViolation {
+    // Buffer indices of the start and the end of the write, none outside of all buffers
+    Overflow {
+        pc:     GuestAddr,
+        addr:   GuestAddr,
+        size:   usize,
+        from:   Option<usize>,
+        to:     Option<usize>,
+    },
+    UninitRead {
+        pc:     GuestAddr,
+        addr:   GuestAddr,
+        size:   usize,
+        buffer: usize,
+    },
+}
+
+/* Consecutive stores of one instruction: (lowest addr, end addr, buffer, generation) */
+#[derive(Copy, Clone, Default)]
+struct StoreStream {
+    begin:      GuestAddr,
+    end:        GuestAddr,
+    buffer:     Option<usize>,
+    generation: u64,
+}
+
+/* PCs of the instrumented stores and loads, indexed by their hook id */
+static mut SANITIZER_STORE_PCS: Vec<GuestAddr> = vec![];
+static mut SANITIZER_LOAD_PCS: Vec<GuestAddr> = vec![];
+static mut SANITIZER_STREAMS: Vec<StoreStream> = vec![];
+/* Streams of older executions are stale */
+static mut SANITIZER_GENERATION: u64 = 0;
+/* One byte per buffer byte, non-zero once written, empty for initialized buffers */
+static mut SANITIZER_SHADOW: Vec<Vec<u8>> = vec![];
+static mut SANITIZER_VIOLATION: Option<SanitizerViolation> = None;
+static mut SANITIZER_EMULATOR: u64 = 0;
+
+pub fn sanitizer_init(emu: &Emulator) {
+    log::info!("Sanitizing {} SRAM buffers", borrow_global_conf().unwrap().sanitizer_buffers.len());
+    unsafe { SANITIZER_EMULATOR = emu as *const _ as u64; }
+}
+
+/// Has to be called before each execution
+pub fn sanitizer_reset() {
+    let conf = borrow_global_conf().unwrap();
+    unsafe {
+        SANITIZER_VIOLATION = None;
+        SANITIZER_GENERATION += 1;
+        if SANITIZER_SHADOW.len() != conf.sanitizer_buffers.len() {
+            SANITIZER_SHADOW = conf.sanitizer_buffers.iter()
+                .map(|buffer| if buffer.uninit { vec![0; (buffer.end - buffer.begin) as usize] } else { vec![] })
+                .collect();
+        }
+        for shadow in SANITIZER_SHADOW.iter_mut() {
+            shadow.fill(0);
+        }
+    }
+}
+
+pub fn sanitizer_violation() -> Option<SanitizerViolation> {
+    unsafe { SANITIZER_VIOLATION.clone() }
+}
+
+/* Buffers are sorted and don't overlap */
+fn find_buffer(addr: GuestAddr) -> Option<usize> {
+    let buffers = &borrow_global_conf().unwrap().sanitizer_buffers;
+    match buffers.binary_search_by(|buffer| {
+        if addr < buffer.begin {
+            std::cmp::Ordering::Greater
+        } else if addr >= buffer.end {
+            std::cmp::Ordering::Less
+        } else {
+            std::cmp::Ordering::Equal
+        }
+    }) {
+        Ok(i) => Some(i),
+        Err(_) => None,
+    }
+}
+
+fn report(violation: SanitizerViolation) {
+    log::debug!("Sanitizer: {:?}", violation);
+    unsafe {
+        if SANITIZER_VIOLATION.is_some() {
+            return;
+        }
+        SANITIZER_VIOLATION = Some(violation);
+        let emu = (SANITIZER_EMULATOR as *const Emulator).as_ref().unwrap();
+        emu.current_cpu().unwrap().trigger_breakpoint();
+    }
+}
+
+/// Registers a store instrumented by the write hooks
+pub fn sanitizer_gen_store(id: u64, pc: GuestAddr) {
+    unsafe {
+        if SANITIZER_STORE_PCS.len() <= id as usize {
+            SANITIZER_STORE_PCS.resize(id as usize + 1, 0);
+            SANITIZER_STREAMS.resize(id as usize + 1, StoreStream::default());
+        }
+        SANITIZER_STORE_PCS[id as usize] = pc;
+    }
+}
+
+/// Checks a store and marks the written bytes as initialized
+pub fn sanitizer_store(id: u64, addr: GuestAddr, size: usize) {
+    let conf = borrow_global_conf().unwrap();
+    if addr >= conf.qemu_sram_size {
+        return;
+    }
+    let last = addr + size as GuestAddr - 1;
+    let from = find_buffer(addr);
+    let to = find_buffer(last);
+    let pc = unsafe { SANITIZER_STORE_PCS[id as usize] };
+    if from != to {
+        report(SanitizerViolation::Overflow { pc, addr, size, from, to });
+    }
+
+    // Continuing a stream of the same store in another buffer
+    let stream = unsafe { &mut SANITIZER_STREAMS[id as usize] };
+    let generation = unsafe { SANITIZER_GENERATION };
+    if stream.generation == generation && stream.buffer.is_some() {
+        if addr == stream.end && stream.buffer != from {
+            report(SanitizerViolation::Overflow { pc, addr, size, from: stream.buffer, to: from });
+        } else if last + 1 == stream.begin && stream.buffer != to {
+            report(SanitizerViolation::Overflow { pc, addr, size, from: stream.buffer, to: to });
+        }
+    }
+    if stream.generation == generation && (addr == stream.end || last + 1 == stream.begin) {
+        stream.begin = stream.begin.min(addr);
+        stream.end = stream.end.max(last + 1);
+    } else {
+        *stream = StoreStream { begin: addr, end: last + 1, buffer: from, generation };
+    }
+
+    let buffers = if from == to { vec![from] } else { vec![from, to] };
+    for buffer in buffers.into_iter().flatten() {
+        let shadow = unsafe { &mut SANITIZER_SHADOW[buffer] };
+        if shadow.is_empty() {
+            continue;
+        }
+        let buffer = &conf.sanitizer_buffers[buffer];
+        for byte in addr.max(buffer.begin)..=last.min(buffer.end - 1) {
+            shadow[(byte - buffer.begin) as usize] = 1;
+        }
+    }
+}
+
+/// Registers a load instrumented by the read hooks
+pub fn sanitizer_gen_load(id: u64, pc: GuestAddr) {
+    unsafe {
+        if SANITIZER_LOAD_PCS.len() <= id as usize {
+            SANITIZER_LOAD_PCS.resize(id as usize + 1, 0);
+        }
+        SANITIZER_LOAD_PCS[id as usize] = pc;
+    }
+}
+
+/// Checks a load for bytes never written in this execution
+pub fn sanitizer_load(id: u64, addr: GuestAddr, size: usize) {
+    let conf = borrow_global_conf().unwrap();
+    if addr >= conf.qemu_sram_size {
+        return;
+    }
+    for byte in addr..(addr + size as GuestAddr) {
+        let buffer = match find_buffer(byte) {
+            Some(buffer) => buffer,
+            None => continue,
+        };
+        let shadow = unsafe { &SANITIZER_SHADOW[buffer] };
+        if !shadow.is_empty() && shadow[(byte - conf.sanitizer_buffers[buffer].begin) as usize] == 0 {
+            let pc = unsafe { SANITIZER_LOAD_PCS[id as usize] };
+            report(SanitizerViolation::UninitRead { pc, addr, size, buffer });
+            return;
+        }
+    }
+}
+
+/// Metadata of a sanitizer violation
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SanitizerMetadata {
+    pub violation: String,
+}
+
+impl_serdeany!(SanitizerMetadata);
+
+/* Buffer name for reports */
+fn buffer_name(buffer: Option<usize>) -> String {
+    match buffer {
+        Some(i) => {
+            let buffer = &borrow_global_conf().unwrap().sanitizer_buffers[i];
+            format!("\"{}\" [{:#010x}, {:#010x})", buffer.name, buffer.begin, buffer.end)
+        },
+        None => "no buffer".to_string(),
+    }
+}
+
+impl Debug for SanitizerViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match self {
+            SanitizerViolation::Overflow { pc, addr, size, from, to } =>
+                write!(f, "{:#010x}: write of {} bytes at {:#010x} crosses from {} into {}",
+                    pc, size, addr, buffer_name(*from), buffer_name(*to)),
+            SanitizerViolation::UninitRead { pc, addr, size, buffer } =>
+                write!(f, "{:#010x}: read of {} bytes at {:#010x} from uninitialized {}",
+                    pc, size, addr, buffer_name(Some(*buffer))),
+        }
+    }
+}
+
+impl Debug for SanitizerBuffer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        write!(f, "({},[{:#010x},{:#010x}){})", self.name, self.begin, self.end, if self.uninit { ",uninit" } else { "" })
+    }
+}
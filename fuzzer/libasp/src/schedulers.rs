@@ -0,0 +1,202 @@
+/// Corpus scheduling selected in the YAML config
+/// The queue, minimizer (favoring small and fast entries covering the most edges), power
+/// schedules and weighted selection share one scheduler type so that the fuzzer is built once,
+/// the stats of the runs are compared afterwards by their last monitor line
+
+use crate::yaml_conf::YAMLConfig;
+
+use libafl::prelude::*;
+
+use std::fs;
+use std::str::FromStr;
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+use std::path::{
+    Path,
+    PathBuf,
+};
+
+#[derive(Copy, Clone, PartialEq, Default)]
+pub enum SchedulerKind {
+    #[default]
+    Queue,
+    Minimizer,
+    Power(PowerSchedule),
+    Weighted(PowerSchedule),
+}
+
+impl SchedulerKind {
+    /// Power schedules and weighted selection rely on the calibration stage
+    pub fn is_calibrated(&self) -> bool {
+        matches!(self, SchedulerKind::Power(_) | SchedulerKind::Weighted(_))
+    }
+
+    /// All but the plain queue are minimized by the indexes of the coverage map
+    pub fn tracks_indexes(&self) -> bool {
+        *self != SchedulerKind::Queue
+    }
+}
+
+pub fn str_to_power_schedule(input: &str) -> Option<PowerSchedule> {
+    match input {
+        "explore"   => Some(PowerSchedule::EXPLORE),
+        "fast"      => Some(PowerSchedule::FAST),
+        "coe"       => Some(PowerSchedule::COE),
+        _           => None,
+    }
+}
+
+fn power_schedule_to_str(schedule: &PowerSchedule) -> &'static str {
+    match schedule {
+        PowerSchedule::EXPLORE  => "explore",
+        PowerSchedule::FAST     => "fast",
+        PowerSchedule::COE      => "coe",
+        _                       => "other",
+    }
+}
+
+/// One of the schedulers of "scheduler: kind:", all of them schedule the same state
+pub enum CampaignScheduler<Q, M, P, W> {
+    Queue(Q),
+    Minimizer(M),
+    Power(P),
+    Weighted(W),
+}
+
+/// The schedulers of "scheduler: kind:" for a map observer O and a state S
+pub type StdCampaignScheduler<O, S> = CampaignScheduler<
+    QueueScheduler<S>,
+    IndexesLenTimeMinimizerScheduler<QueueScheduler<S>>,
+    IndexesLenTimeMinimizerScheduler<PowerQueueScheduler<O, S>>,
+    IndexesLenTimeMinimizerScheduler<StdWeightedScheduler<O, S>>,
+>;
+
+impl<Q, M, P, W> UsesState for CampaignScheduler<Q, M, P, W>
+where
+    Q: UsesState,
+{
+    type State = Q::State;
+}
+
+impl<Q, M, P, W> Scheduler for CampaignScheduler<Q, M, P, W>
+where
+    Q: Scheduler,
+    M: Scheduler<State = Q::State>,
+    P: Scheduler<State = Q::State>,
+    W: Scheduler<State = Q::State>,
+{
+    fn on_add(&self, state: &mut Self::State, idx: usize) -> Result<(), Error> {
+        match self {
+            CampaignScheduler::Queue(scheduler)     => scheduler.on_add(state, idx),
+            CampaignScheduler::Minimizer(scheduler) => scheduler.on_add(state, idx),
+            CampaignScheduler::Power(scheduler)     => scheduler.on_add(state, idx),
+            CampaignScheduler::Weighted(scheduler)  => scheduler.on_add(state, idx),
+        }
+    }
+
+    fn on_replace(
+        &self,
+        state: &mut Self::State,
+        idx: usize,
+        prev: &Testcase<<Self::State as UsesInput>::Input>,
+    ) -> Result<(), Error> {
+        match self {
+            CampaignScheduler::Queue(scheduler)     => scheduler.on_replace(state, idx, prev),
+            CampaignScheduler::Minimizer(scheduler) => scheduler.on_replace(state, idx, prev),
+            CampaignScheduler::Power(scheduler)     => scheduler.on_replace(state, idx, prev),
+            CampaignScheduler::Weighted(scheduler)  => scheduler.on_replace(state, idx, prev),
+        }
+    }
+
+    fn on_remove(
+        &self,
+        state: &mut Self::State,
+        idx: usize,
+        testcase: &Option<Testcase<<Self::State as UsesInput>::Input>>,
+    ) -> Result<(), Error> {
+        match self {
+            CampaignScheduler::Queue(scheduler)     => scheduler.on_remove(state, idx, testcase),
+            CampaignScheduler::Minimizer(scheduler) => scheduler.on_remove(state, idx, testcase),
+            CampaignScheduler::Power(scheduler)     => scheduler.on_remove(state, idx, testcase),
+            CampaignScheduler::Weighted(scheduler)  => scheduler.on_remove(state, idx, testcase),
+        }
+    }
+
+    fn next(&self, state: &mut Self::State) -> Result<usize, Error> {
+        match self {
+            CampaignScheduler::Queue(scheduler)     => scheduler.next(state),
+            CampaignScheduler::Minimizer(scheduler) => scheduler.next(state),
+            CampaignScheduler::Power(scheduler)     => scheduler.next(state),
+            CampaignScheduler::Weighted(scheduler)  => scheduler.next(state),
+        }
+    }
+}
+
+/* Key-value pairs of the last global stats line of a LibAFL log, e.g.
+ * "[Testcase #0] run time: 0h-1m-2s, clients: 1, corpus: 12, objectives: 0, executions: 345, exec/sec: 5" */
+fn last_stats(log: &str) -> Vec<(String, String)> {
+    let line = match log.lines().rev().find(|line| line.starts_with('[')) {
+        Some(line) => line,
+        None => return vec![],
+    };
+    let line = line.split_once("] ").map_or(line, |(_, stats)| stats);
+    line.split(", ")
+        .filter_map(|pair| pair.split_once(": "))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+/// YAML report comparing the scheduler and the last stats of run directories
+pub fn compare_campaigns(run_dirs: &[PathBuf]) -> String {
+    let mut out_str = format!("# Last stats of {} runs\n", run_dirs.len());
+    out_str.push_str("campaigns:\n");
+    for run_dir in run_dirs.iter() {
+        out_str.push_str(&format!("    - run:          \"{}\"\n", run_dir.display()));
+        let config = run_dir.join("config.yaml");
+        if config.exists() {
+            let conf = YAMLConfig::new(&config.to_string_lossy());
+            out_str.push_str(&format!("      scheduler:    \"{:?}\"\n", conf.scheduler));
+        }
+        let log = fs::read_to_string(Path::new(run_dir).join("logs").join("libafl.log")).unwrap_or_default();
+        let stats = last_stats(&log);
+        if stats.is_empty() {
+            out_str.push_str("      stats:        null\n");
+            continue;
+        }
+        out_str.push_str("      stats:\n");
+        for (key, value) in stats.iter() {
+            out_str.push_str(&format!("          {:<14}\"{}\"\n", format!("\"{}\":", key), value));
+        }
+    }
+    if run_dirs.is_empty() {
+        out_str.push_str("    - null\n");
+    }
+    out_str
+}
+
+impl Debug for SchedulerKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match self {
+            SchedulerKind::Queue                => write!(f, "queue"),
+            SchedulerKind::Minimizer            => write!(f, "minimizer"),
+            SchedulerKind::Power(schedule)      => write!(f, "power ({})", power_schedule_to_str(schedule)),
+            SchedulerKind::Weighted(schedule)   => write!(f, "weighted ({})", power_schedule_to_str(schedule)),
+        }
+    }
+}
+
+impl FromStr for SchedulerKind {
+    type Err = ();
+    /* The power schedule defaults to "fast" and is set from "scheduler: schedule:" */
+    fn from_str(input: &str) -> Result<SchedulerKind, ()> {
+        match input {
+            "queue"     => Ok(SchedulerKind::Queue),
+            "minimizer" => Ok(SchedulerKind::Minimizer),
+            "power"     => Ok(SchedulerKind::Power(PowerSchedule::FAST)),
+            "weighted"  => Ok(SchedulerKind::Weighted(PowerSchedule::FAST)),
+            _           => Err(()),
+        }
+    }
+}
@@ -0,0 +1,334 @@
+/// Shadow call stack for return-address integrity checking
+/// Calls (BL/BLX) push their return address, returns (pop {..., pc}, ldr pc, [sp], #4, bx lr)
+/// verify it, a mismatch ends the execution and is saved to its own corpus by the smashed function
+
+use libafl_qemu::*;
+use libafl::prelude::*;
+use libafl::corpus::ondisk::OnDiskMetadataFormat;
+
+use crate::gen_metadata::saved_addrs;
+use crate::yaml_conf::borrow_global_conf;
+
+use log;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+use std::path::PathBuf;
+
+/* Deeper frames are dropped, e.g. from functions which never return */
+const MAX_DEPTH: usize = 256;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum CallKind {
+    Bl(GuestAddr),
+    // BLX <Rm>, the target is read when the call is executed
+    BlxReg(u32),
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ReturnKind {
+    // pop {..., pc}, offset of PC from SP
+    Pop(u32),
+    // ldr pc, [sp], #4
+    LdrPc,
+    BxLr,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum StackSite {
+    Call(CallKind),
+    Return(ReturnKind),
+}
+
+/* Instruction of the on-chip bootloader which is hooked, with its condition */
+#[derive(Copy, Clone)]
+pub struct StackSiteInsn {
+    pub addr:   GuestAddr,
+    pub cond:   u32,
+    pub site:   StackSite,
+}
+
+/* Frame of a call: (return address, called function) */
+#[derive(Copy, Clone)]
+struct Frame {
+    ret:        GuestAddr,
+    function:   GuestAddr,
+}
+
+#[derive(Copy, Clone)]
+pub struct ShadowStackViolation {
+    pub function:   GuestAddr,
+    pub site:       GuestAddr,
+    pub expected:   GuestAddr,
+    pub actual:     GuestAddr,
+}
+
+/* Sorted by address */
+static mut SHADOW_STACK_SITES: Vec<StackSiteInsn> = vec![];
+/* Addresses following any call, including untracked ones (mov lr, pc) */
+static mut SHADOW_STACK_RETURN_POINTS: Option<HashSet<GuestAddr>> = None;
+static mut SHADOW_STACK: Vec<Frame> = vec![];
+static mut SHADOW_STACK_VIOLATION: Option<ShadowStackViolation> = None;
+
+/// Has to be called before each execution
+pub fn shadow_stack_reset() {
+    unsafe {
+        SHADOW_STACK.clear();
+        SHADOW_STACK_VIOLATION = None;
+    }
+}
+
+pub fn shadow_stack_violation() -> Option<ShadowStackViolation> {
+    unsafe { SHADOW_STACK_VIOLATION }
+}
+
+/* ARM mode calls and returns */
+pub fn find_stack_sites(bin: &[u8], base: GuestAddr) -> Vec<StackSiteInsn> {
+    let mut sites = vec![];
+    for (i, insn) in bin.chunks_exact(4).enumerate() {
+        let insn = u32::from_le_bytes(insn.try_into().unwrap());
+        let addr = base + (i * 4) as GuestAddr;
+        let cond = insn >> 28;
+        let site = if (insn & 0xfe00_0000) == 0xfa00_0000 {
+            // BLX <label>, unconditional encoding with the H bit
+            let offset = (((insn << 8) as i32) >> 6) as u32 | ((insn >> 23) & 0x2);
+            StackSite::Call(CallKind::Bl(addr.wrapping_add(8).wrapping_add(offset)))
+        } else if cond == 0xf {
+            continue;
+        } else if (insn & 0x0f00_0000) == 0x0b00_0000 {
+            let offset = (((insn << 8) as i32) >> 6) as u32;
+            StackSite::Call(CallKind::Bl(addr.wrapping_add(8).wrapping_add(offset)))
+        } else if (insn & 0x0fff_fff0) == 0x012f_ff30 && (insn & 0xf) != 0xf {
+            StackSite::Call(CallKind::BlxReg(insn & 0xf))
+        } else if (insn & 0x0fff_8000) == 0x08bd_8000 {
+            StackSite::Return(ReturnKind::Pop(4 * (insn & 0x7fff).count_ones()))
+        } else if (insn & 0x0fff_ffff) == 0x049d_f004 {
+            StackSite::Return(ReturnKind::LdrPc)
+        } else if (insn & 0x0fff_ffff) == 0x012f_ff1e {
+            StackSite::Return(ReturnKind::BxLr)
+        } else {
+            continue;
+        };
+        sites.push(StackSiteInsn { addr, cond: if cond == 0xf { 0xe } else { cond }, site });
+    }
+    sites
+}
+
+pub fn add_shadow_stack_hooks(bin: &[u8], base: GuestAddr, emu: &Emulator) {
+    let sites = find_stack_sites(bin, base);
+    log::info!("Adding {} shadow call stack hooks", sites.len());
+    for site in sites.iter() {
+        emu.set_hook(site.addr, shadow_stack_hook, emu as *const _ as u64, false);
+    }
+    let mut return_points: HashSet<GuestAddr> = sites.iter()
+        .filter(|site| matches!(site.site, StackSite::Call(_)))
+        .map(|site| site.addr + 4)
+        .collect();
+    for (i, insn) in bin.chunks_exact(4).enumerate() {
+        if (u32::from_le_bytes(insn.try_into().unwrap()) & 0x0fff_ffff) == 0x01a0_e00f {
+            return_points.insert(base + (i * 4) as GuestAddr + 8);
+        }
+    }
+    unsafe {
+        SHADOW_STACK_SITES = sites;
+        SHADOW_STACK_RETURN_POINTS = Some(return_points);
+    }
+}
+
+/* Hooks run before the instruction, conditional ones might not be executed */
+fn cond_passed(cond: u32, cpsr: u32) -> bool {
+    let n = (cpsr >> 31) & 1 != 0;
+    let z = (cpsr >> 30) & 1 != 0;
+    let c = (cpsr >> 29) & 1 != 0;
+    let v = (cpsr >> 28) & 1 != 0;
+    match cond {
+        0x0 => z,
+        0x1 => !z,
+        0x2 => c,
+        0x3 => !c,
+        0x4 => n,
+        0x5 => !n,
+        0x6 => v,
+        0x7 => !v,
+        0x8 => c && !z,
+        0x9 => !c || z,
+        0xa => n == v,
+        0xb => n != v,
+        0xc => !z && n == v,
+        0xd => z || n != v,
+        _ => true,
+    }
+}
+
+extern "C" fn shadow_stack_hook(pc: GuestAddr, data: u64) {
+    let emu = unsafe { (data as *const Emulator).as_ref().unwrap() };
+    let i = match unsafe { SHADOW_STACK_SITES.binary_search_by_key(&pc, |site| site.addr) } {
+        Ok(i) => i,
+        Err(_) => return,
+    };
+    let site = unsafe { SHADOW_STACK_SITES[i] };
+    let cpsr: u32 = emu.read_reg(Regs::Cpsr).unwrap();
+    if !cond_passed(site.cond, cpsr) {
+        return;
+    }
+    let stack = unsafe { &mut SHADOW_STACK };
+    match site.site {
+        StackSite::Call(call) => {
+            let function = match call {
+                CallKind::Bl(target) => target,
+                CallKind::BlxReg(rm) => emu.read_reg(rm as i32).unwrap(),
+            };
+            if stack.len() >= MAX_DEPTH {
+                stack.remove(0);
+            }
+            stack.push(Frame { ret: pc + 4, function });
+        },
+        StackSite::Return(ret) => {
+            let actual = match ret {
+                ReturnKind::Pop(offset) => read_stack(emu, offset),
+                ReturnKind::LdrPc => read_stack(emu, 0),
+                ReturnKind::BxLr => emu.read_reg(Regs::Lr).unwrap(),
+            };
+            // Frames entered before the start of the harness are unknown
+            let frame = match stack.last() {
+                Some(frame) => *frame,
+                None => return,
+            };
+            // Stubs and jumping tunnels skip returns of the frames above
+            if let Some(depth) = stack.iter().rposition(|frame| frame.ret == actual) {
+                stack.truncate(depth);
+                return;
+            }
+            // Returns of untracked calls and of frames dropped or entered before the harness
+            if unsafe { SHADOW_STACK_RETURN_POINTS.as_ref().unwrap().contains(&actual) } {
+                return;
+            }
+            let violation = ShadowStackViolation {
+                function:   frame.function,
+                site:       pc,
+                expected:   frame.ret,
+                actual:     actual,
+            };
+            log::debug!("Shadow call stack: {:?}", violation);
+            unsafe { SHADOW_STACK_VIOLATION = Some(violation); }
+            emu.current_cpu().unwrap().trigger_breakpoint();
+        },
+    }
+}
+
+fn read_stack(emu: &Emulator, offset: u32) -> GuestAddr {
+    let sp: u32 = emu.read_reg(Regs::Sp).unwrap();
+    let mut buf = [0u8; 4];
+    unsafe { emu.current_cpu().unwrap().read_mem(sp + offset, &mut buf); }
+    u32::from_le_bytes(buf)
+}
+
+/// Metadata of a smashed return address
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShadowStackMetadata {
+    pub function: String,
+    pub site: String,
+    pub expected: String,
+    pub actual: String,
+    pub fields: Vec<String>,
+}
+
+impl_serdeany!(ShadowStackMetadata);
+
+/// Saves executions with a smashed return address of a new function to the smashes corpus,
+/// never interesting otherwise
+pub struct ShadowStackFeedback<I>
+where
+    I: Input,
+{
+    smashes: OnDiskCorpus<I>,
+    functions: HashSet<GuestAddr>,
+}
+
+impl<S> Feedback<S> for ShadowStackFeedback<S::Input>
+where
+    S: UsesInput + HasClientPerfMonitor,
+    S::Input: HasTargetBytes,
+{
+    #[allow(clippy::wrong_self_convention)]
+    fn is_interesting<EM, OT>(
+        &mut self,
+        _state: &mut S,
+        _manager: &mut EM,
+        input: &S::Input,
+        _observers: &OT,
+        _exit_kind: &ExitKind,
+    ) -> Result<bool, Error>
+    where
+        EM: EventFirer,
+        OT: ObserversTuple<S>,
+    {
+        let violation = match shadow_stack_violation() {
+            Some(violation) => violation,
+            None => return Ok(false),
+        };
+        if !self.functions.insert(violation.function) {
+            log::debug!("Known smashed function {:#x}", violation.function);
+            return Ok(false);
+        }
+        log::info!("New smashed return address: {:?}", violation);
+
+        let mut fields = vec![];
+        if let Some(conf) = borrow_global_conf() {
+            fields = conf.input_layout.decode(input.target_bytes().as_slice());
+        }
+        let mut testcase = Testcase::new(input.clone());
+        testcase.add_metadata(ShadowStackMetadata {
+            function:   format!("{:#010x}", violation.function),
+            site:       format!("{:#010x}", violation.site),
+            expected:   format!("{:#010x}", violation.expected),
+            actual:     format!("{:#010x}", violation.actual),
+            fields:     fields,
+        });
+        self.smashes.add(testcase)?;
+        Ok(false)
+    }
+}
+
+impl<I> Named for ShadowStackFeedback<I>
+where
+    I: Input,
+{
+    #[inline]
+    fn name(&self) -> &str {
+        "ShadowStackFeedback"
+    }
+}
+
+impl<I> ShadowStackFeedback<I>
+where
+    I: Input,
+{
+    /// Creates a new [`ShadowStackFeedback`] saving smashed return addresses to `smashes_dir`
+    #[must_use]
+    pub fn new(smashes_dir: PathBuf) -> Self {
+        Self {
+            functions: saved_addrs(&smashes_dir, "function"),
+            smashes: OnDiskCorpus::new_save_meta(smashes_dir, Some(OnDiskMetadataFormat::JsonPretty)).unwrap(),
+        }
+    }
+}
+
+impl<I> Debug for ShadowStackFeedback<I>
+where
+    I: Input,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        write!(f, "ShadowStackFeedback({} functions)", self.functions.len())
+    }
+}
+
+impl Debug for ShadowStackViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        write!(f, "{:#010x}: function {:#010x} returns to {:#010x} instead of {:#010x}",
+            self.site, self.function, self.actual, self.expected)
+    }
+}
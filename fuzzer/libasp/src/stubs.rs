@@ -0,0 +1,170 @@
+/// Function stubs replace a function with an immediate return to LR
+/// Used to isolate the fuzzed code from hardware waits, crypto and logging functions
+
+use crate::tunneling::tunnels_jump;
+use crate::yaml_conf::borrow_global_conf;
+
+use libafl_qemu::*;
+
+use log;
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+
+/* Rust model of a function, gets the emulator at function entry and returns R0 */
+pub type StubModel = fn(&Emulator) -> u32;
+
+/* Available models by name */
+const STUB_MODELS: [(&str, StubModel); 2] = [
+    ("memcpy",  model_memcpy),
+    ("memset",  model_memset),
+];
+
+#[derive(Clone)]
+pub enum StubReturn {
+    // R0 is left untouched
+    Void,
+    Value(u32),
+    // N-th argument (R0-R3, then stack)
+    Arg(usize),
+    Model(String),
+}
+
+/*
+ *  A stub is applied at the first instruction of the function:
+ *  - buf:      data written to the buffer pointed to by the N-th argument
+ *  - ret:      return value of the function
+ */
+#[derive(Clone)]
+pub struct Stub {
+    pub addr:   GuestAddr,
+    pub buf:    Option<(usize, Vec<u8>)>,
+    pub ret:    StubReturn,
+}
+
+/* Stub compiled at setup, passed to its hook as data */
+struct StubAction {
+    emu:    *const Emulator,
+    buf:    Option<(usize, Vec<u8>)>,
+    ret:    StubReturn,
+    model:  Option<StubModel>,
+}
+
+pub fn stub_model(name: &str) -> Option<StubModel> {
+    STUB_MODELS.iter().find(|model| model.0 == name).map(|model| model.1)
+}
+
+pub fn add_stub(stub: &Stub, emu: &Emulator) {
+    let model = match &stub.ret {
+        StubReturn::Model(name) => Some(stub_model(name).expect("Cannot match to valid stub model")),
+        _ => None,
+    };
+    let action = StubAction {
+        emu:    emu as *const _,
+        buf:    stub.buf.clone(),
+        ret:    stub.ret.clone(),
+        model:  model,
+    };
+    log::debug!("Adding stub: {:?}", stub);
+    // Hooks are never removed, the action lives as long as the emulator
+    let data = Box::into_raw(Box::new(action)) as u64;
+    emu.set_hook(stub.addr, stub_hook, data, false);
+}
+
+extern "C" fn stub_hook(pc: GuestAddr, data: u64) {
+    log::debug!("Stub hook: pc={:#x}", pc);
+    let action = unsafe { (data as *const StubAction).as_ref().unwrap() };
+    let emu = unsafe { action.emu.as_ref().unwrap() };
+    if let Some(buf) = &action.buf {
+        let dest = read_arg(emu, buf.0);
+        unsafe { emu.current_cpu().unwrap().write_mem(dest, &buf.1); }
+    }
+    let ret = match action.ret {
+        StubReturn::Void        => None,
+        StubReturn::Value(ret)  => Some(ret),
+        StubReturn::Arg(arg)    => Some(read_arg(emu, arg)),
+        StubReturn::Model(_)    => Some(action.model.unwrap()(emu)),
+    };
+    if let Some(ret) = ret {
+        emu.write_reg(Regs::R0, ret).unwrap();
+    }
+    let lr: u32 = emu.read_reg(Regs::Lr).unwrap();
+    tunnels_jump(emu, lr);
+}
+
+/* AAPCS: R0-R3, then the stack */
+pub fn read_arg(emu: &Emulator, arg: usize) -> u32 {
+    if arg < 4 {
+        return emu.read_reg(arg as i32).unwrap();
+    }
+    let sp: u32 = emu.read_reg(Regs::Sp).unwrap();
+    let mut buf = [0u8; 4];
+    unsafe { emu.current_cpu().unwrap().read_mem(sp + 4 * (arg as u32 - 4), &mut buf); }
+    u32::from_le_bytes(buf)
+}
+
+/* Models access guest memory in chunks of this size instead of buffering the whole length */
+const MODEL_CHUNK_SIZE: usize = 0x1000;
+
+/* Length argument of a model, None (and reported) if no access can be that long */
+fn model_len(name: &str, n: u32) -> Option<usize> {
+    let sram_size = borrow_global_conf().unwrap().qemu_sram_size;
+    if n > sram_size {
+        log::warn!("Stub model {}: length {:#x} exceeds the SRAM size {:#x}, skipped", name, n, sram_size);
+        return None;
+    }
+    Some(n as usize)
+}
+
+/* memcpy(dest, src, n) -> dest */
+fn model_memcpy(emu: &Emulator) -> u32 {
+    let dest = read_arg(emu, 0);
+    let src = read_arg(emu, 1);
+    let n = match model_len("memcpy", read_arg(emu, 2)) {
+        Some(n) => n,
+        None    => return dest,
+    };
+    let cpu = emu.current_cpu().unwrap();
+    let mut buf = [0u8; MODEL_CHUNK_SIZE];
+    for off in (0..n).step_by(MODEL_CHUNK_SIZE) {
+        let chunk = &mut buf[..MODEL_CHUNK_SIZE.min(n - off)];
+        unsafe {
+            cpu.read_mem(src.wrapping_add(off as u32), chunk);
+            cpu.write_mem(dest.wrapping_add(off as u32), chunk);
+        }
+    }
+    dest
+}
+
+/* memset(dest, c, n) -> dest */
+fn model_memset(emu: &Emulator) -> u32 {
+    let dest = read_arg(emu, 0);
+    let c = read_arg(emu, 1);
+    let n = match model_len("memset", read_arg(emu, 2)) {
+        Some(n) => n,
+        None    => return dest,
+    };
+    let cpu = emu.current_cpu().unwrap();
+    let buf = [c as u8; MODEL_CHUNK_SIZE];
+    for off in (0..n).step_by(MODEL_CHUNK_SIZE) {
+        unsafe { cpu.write_mem(dest.wrapping_add(off as u32), &buf[..MODEL_CHUNK_SIZE.min(n - off)]); }
+    }
+    dest
+}
+
+impl Debug for Stub {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        write!(f, "({:#010x}", self.addr)?;
+        if let Some(buf) = &self.buf {
+            write!(f, ",[arg{}]={:#x} bytes", buf.0, buf.1.len())?;
+        }
+        match &self.ret {
+            StubReturn::Void        => write!(f, ",ret"),
+            StubReturn::Value(ret)  => write!(f, ",ret={:#x}", ret),
+            StubReturn::Arg(arg)    => write!(f, ",ret=arg{}", arg),
+            StubReturn::Model(name) => write!(f, ",model={}", name),
+        }?;
+        write!(f, ")")
+    }
+}
@@ -0,0 +1,103 @@
+/// Symbol map of the on-chip bootloader
+/// One "<address> <name> [<size>]" per line (e.g. exported from Ghidra), lines starting with
+/// '#' are comments. Symbols without a size span to the next symbol
+
+use libafl_qemu::GuestAddr;
+
+use std::fs;
+
+#[derive(Clone, Default)]
+pub struct SymbolMap {
+    // Sorted by address: (begin, end, name)
+    pub symbols: Vec<(GuestAddr, GuestAddr, String)>,
+}
+
+impl SymbolMap {
+    pub fn load(path: &str) -> Self {
+        Self::parse(&fs::read_to_string(path).expect("Unable to read symbol map"))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut symbols: Vec<(GuestAddr, Option<GuestAddr>, String)> = vec![];
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            if columns.len() < 2 {
+                panic!("Expecting '<address> <name> [<size>]' in symbol map, found: {}", line);
+            }
+            let addr = parse_addr(columns[0]).expect("Expecting a hex address in symbol map");
+            let size = columns.get(2).map(|size| parse_addr(size).expect("Expecting a hex size in symbol map"));
+            symbols.push((addr, size, columns[1].to_string()));
+        }
+        symbols.sort_by_key(|symbol| symbol.0);
+        let mut map = SymbolMap::default();
+        for (i, symbol) in symbols.iter().enumerate() {
+            let end = match symbol.1 {
+                Some(size) => symbol.0.saturating_add(size),
+                None => symbols.get(i + 1).map_or(GuestAddr::MAX, |next| next.0),
+            };
+            map.symbols.push((symbol.0, end, symbol.2.clone()));
+        }
+        map
+    }
+
+    /// Range [begin, end) of a symbol
+    pub fn range(&self, name: &str) -> Option<(GuestAddr, GuestAddr)> {
+        self.symbols.iter().find(|symbol| symbol.2 == name).map(|symbol| (symbol.0, symbol.1))
+    }
+
+    /// Symbol containing addr
+    pub fn lookup(&self, addr: GuestAddr) -> Option<&(GuestAddr, GuestAddr, String)> {
+        let i = self.symbols.partition_point(|symbol| symbol.0 <= addr);
+        self.symbols[..i].iter().rev().find(|symbol| addr < symbol.1)
+    }
+}
+
+fn parse_addr(input: &str) -> Option<GuestAddr> {
+    let input = input.trim_start_matches("0x").trim_start_matches("0X");
+    GuestAddr::from_str_radix(input, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOLS: &str = "
+        # address name [size]
+        0xffff0100 parse_psp_flash 0x40
+        ffff0000   reset
+        0xffff0200 memcpy
+    ";
+
+    #[test]
+    fn parse_sorts_and_sizes_symbols() {
+        let map = SymbolMap::parse(SYMBOLS);
+        let names: Vec<&str> = map.symbols.iter().map(|symbol| symbol.2.as_str()).collect();
+        assert_eq!(names, vec!["reset", "parse_psp_flash", "memcpy"]);
+        // Without a size up to the next symbol, the last one up to the end of memory
+        assert_eq!(map.range("reset"), Some((0xffff_0000, 0xffff_0100)));
+        assert_eq!(map.range("parse_psp_flash"), Some((0xffff_0100, 0xffff_0140)));
+        assert_eq!(map.range("memcpy"), Some((0xffff_0200, GuestAddr::MAX)));
+        assert_eq!(map.range("main"), None);
+    }
+
+    #[test]
+    fn lookup_containing_symbol() {
+        let map = SymbolMap::parse(SYMBOLS);
+        assert_eq!(map.lookup(0xffff_0000).map(|symbol| symbol.2.as_str()), Some("reset"));
+        assert_eq!(map.lookup(0xffff_013f).map(|symbol| symbol.2.as_str()), Some("parse_psp_flash"));
+        // Gap after a sized symbol
+        assert_eq!(map.lookup(0xffff_0140), None);
+        assert_eq!(map.lookup(0xffff_0300).map(|symbol| symbol.2.as_str()), Some("memcpy"));
+        assert_eq!(map.lookup(0x1000), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn parse_rejects_missing_names() {
+        SymbolMap::parse("0xffff0000\n");
+    }
+}
@@ -0,0 +1,98 @@
+/// Harvesting comparison operands from the on-chip bootloader (ARM mode) for a token dictionary
+/// Magic values and entry type IDs are rarely hit by havoc mutations alone.
+/// Only ARM encodings are decoded (CMP/CMN #imm, LDR literal, MOVW/MOVT), constants compared
+/// in Thumb code are not harvested
+
+use libafl::prelude::*;
+
+use log;
+use std::collections::BTreeSet;
+
+/* Instructions to look ahead for a comparison using a loaded constant */
+const CMP_LOOKAHEAD: usize = 4;
+/* Small immediates are already covered by the arithmetic mutations */
+const MIN_TOKEN_VALUE: u32 = 0x100;
+
+/*
+ *  Harvested constants:
+ *  - CMP/CMN Rn, #imm
+ *  - LDR Rt, [PC, #imm] followed by CMP Rn, Rm using Rt
+ *  - MOVW/MOVT Rd pairs followed by CMP Rn, Rm using Rd
+ */
+pub fn harvest_cmp_values(bin: &[u8]) -> BTreeSet<u32> {
+    let insns: Vec<u32> = bin.chunks_exact(4).map(|insn| u32::from_le_bytes(insn.try_into().unwrap())).collect();
+    let mut values = BTreeSet::new();
+    for (i, insn) in insns.iter().enumerate() {
+        // Unconditional/special instructions
+        if insn >> 28 == 0xf {
+            continue;
+        }
+        if (insn & 0x0fd0_0000) == 0x0350_0000 {
+            // CMP/CMN Rn, #imm
+            let rot = ((insn >> 8) & 0xf) * 2;
+            let value = (insn & 0xff).rotate_right(rot);
+            if (insn & 0x0020_0000) != 0 {
+                values.insert(value.wrapping_neg());
+            } else {
+                values.insert(value);
+            }
+        } else if (insn & 0x0f7f_0000) == 0x051f_0000 {
+            // LDR Rt, [PC, #imm]
+            let rt = (insn >> 12) & 0xf;
+            let imm = (insn & 0xfff) as i64;
+            let offset = if (insn & 0x0080_0000) != 0 { imm } else { -imm };
+            let literal = (i as i64) * 4 + 8 + offset;
+            if literal < 0 || literal % 4 != 0 {
+                continue;
+            }
+            if let Some(value) = insns.get((literal / 4) as usize) {
+                if is_cmp_reg_using(&insns[i+1..], rt) {
+                    values.insert(*value);
+                }
+            }
+        } else if (insn & 0x0ff0_0000) == 0x0300_0000 {
+            // MOVW Rd, #imm16 (+ MOVT Rd, #imm16)
+            let rd = (insn >> 12) & 0xf;
+            let mut value = ((insn >> 4) & 0xf000) | (insn & 0xfff);
+            let mut next = i + 1;
+            if let Some(movt) = insns.get(i + 1) {
+                if (movt & 0x0ff0_0000) == 0x0340_0000 && (movt >> 12) & 0xf == rd {
+                    value |= (((movt >> 4) & 0xf000) | (movt & 0xfff)) << 16;
+                    next += 1;
+                }
+            }
+            if is_cmp_reg_using(insns.get(next..).unwrap_or(&[]), rd) {
+                values.insert(value);
+            }
+        }
+    }
+    values.retain(|value| *value >= MIN_TOKEN_VALUE);
+    log::debug!("Harvested {} comparison values", values.len());
+    values
+}
+
+/* CMP/CMN Rn, Rm (register, no shift) within the next instructions with reg as an operand */
+fn is_cmp_reg_using(insns: &[u32], reg: u32) -> bool {
+    for insn in insns.iter().take(CMP_LOOKAHEAD) {
+        if (insn & 0x0fd0_0ff0) == 0x0150_0000 {
+            let rn = (insn >> 16) & 0xf;
+            let rm = insn & 0xf;
+            if rn == reg || rm == reg {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Little endian tokens of the values, 16-bit values are added as 2-byte tokens too
+pub fn values_to_tokens(values: &BTreeSet<u32>) -> Tokens {
+    let mut tokens = Tokens::new();
+    for value in values.iter() {
+        tokens.add_token(&value.to_le_bytes().to_vec());
+        if *value <= 0xffff {
+            tokens.add_token(&(*value as u16).to_le_bytes().to_vec());
+        }
+    }
+    tokens
+}
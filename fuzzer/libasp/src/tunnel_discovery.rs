@@ -0,0 +1,158 @@
+/// Automatic tunnel discovery
+/// Records the outcome of all comparisons in the on-chip bootloader across a corpus and suggests
+/// "tunnels: cmps:" entries for comparisons on input-derived operands which are never satisfied
+
+use crate::cmplog_hooks::*;
+use crate::tunneling::Tunnel;
+
+use libafl_qemu::*;
+
+use log;
+use std::collections::{
+    BTreeSet,
+    HashSet,
+};
+
+/* Distinct operand pairs to keep per comparison */
+const MAX_VALUES: usize = 16;
+
+#[derive(Clone, Default)]
+struct CmpStats {
+    hits:       u64,
+    inputs:     usize,
+    last_input: usize,
+    zero:       u64,
+    values:     BTreeSet<(u32, u32)>,
+    from_input: bool,
+}
+
+/* Sorted by address, same order as the statistics */
+static mut DISCOVERY_SITES: Vec<CmpSite> = vec![];
+static mut DISCOVERY_STATS: Vec<CmpStats> = vec![];
+/* 32-bit words (both byte orders) at any offset of the input which is currently executed */
+static mut DISCOVERY_INPUT_WORDS: Option<HashSet<u32>> = None;
+static mut DISCOVERY_INPUT_ID: usize = 0;
+
+pub fn add_discovery_hooks(bin: &[u8], base: GuestAddr, emu: &Emulator) {
+    let sites = find_cmp_sites(bin, base);
+    log::info!("Adding {} tunnel discovery hooks", sites.len());
+    for site in sites.iter() {
+        emu.set_hook(site.addr, discovery_hook, emu as *const _ as u64, false);
+    }
+    unsafe {
+        DISCOVERY_STATS = vec![CmpStats::default(); sites.len()];
+        DISCOVERY_SITES = sites;
+    }
+}
+
+/// Has to be called before each execution
+pub fn set_discovery_input(input: &[u8]) {
+    unsafe {
+        DISCOVERY_INPUT_WORDS = Some(input.windows(4)
+            .flat_map(|bytes| {
+                let bytes: [u8; 4] = bytes.try_into().unwrap();
+                [u32::from_le_bytes(bytes), u32::from_be_bytes(bytes)]
+            })
+            .collect());
+        DISCOVERY_INPUT_ID += 1;
+    }
+}
+
+extern "C" fn discovery_hook(pc: GuestAddr, data: u64) {
+    let emu = unsafe { (data as *const Emulator).as_ref().unwrap() };
+    let i = match unsafe { DISCOVERY_SITES.binary_search_by_key(&pc, |site| site.addr) } {
+        Ok(i) => i,
+        Err(_) => return,
+    };
+    let site = unsafe { DISCOVERY_SITES[i] };
+    let stats = unsafe { &mut DISCOVERY_STATS[i] };
+    let v0: u32 = emu.read_reg(site.rn as i32).unwrap();
+    let v1: u32 = match site.operand {
+        CmpOperand::Reg(rm) => emu.read_reg(rm as i32).unwrap(),
+        CmpOperand::Imm(imm) => imm,
+    };
+
+    stats.hits += 1;
+    if stats.last_input != unsafe { DISCOVERY_INPUT_ID } {
+        stats.last_input = unsafe { DISCOVERY_INPUT_ID };
+        stats.inputs += 1;
+    }
+    if site.op.is_zero(v0, v1) {
+        stats.zero += 1;
+    }
+    if stats.values.len() < MAX_VALUES {
+        stats.values.insert((v0, v1));
+    }
+    if let (false, Some(input)) = (stats.from_input, unsafe { DISCOVERY_INPUT_WORDS.as_ref() }) {
+        stats.from_input = is_in_input(input, v0) ||
+            (matches!(site.operand, CmpOperand::Reg(_)) && is_in_input(input, v1));
+    }
+}
+
+/* Trivial values are found in every input */
+fn is_in_input(input_words: &HashSet<u32>, value: u32) -> bool {
+    if value <= 0xff || value == 0xffff_ffff {
+        return false;
+    }
+    input_words.contains(&value)
+}
+
+/*
+ *  Candidates are CMP/TEQ comparisons which
+ *  - were reached but never satisfied
+ *  - have an operand found in the input or operands which differ between inputs
+ */
+pub fn discovery_report(num_inputs: usize, tunnels_cmps: &Vec<Tunnel>) -> String {
+    let (sites, stats) = unsafe { (&DISCOVERY_SITES, &DISCOVERY_STATS) };
+
+    let mut out_str = "".to_string();
+    out_str.push_str(&format!("# Tunnel candidates from {} inputs\n", num_inputs));
+    out_str.push_str("# Only CMP/TEQ comparisons are suggested, TST/CMN sites are skipped\n");
+    out_str.push_str("# Each candidate has to be confirmed before it is added to the config\n");
+    out_str.push_str("tunnels:\n");
+    out_str.push_str("    cmps:\n");
+    let mut candidates = 0;
+    for (site, stats) in sites.iter().zip(stats.iter()) {
+        if stats.hits == 0 || stats.zero != 0 {
+            continue;
+        }
+        if site.op != CmpOp::Cmp && site.op != CmpOp::Teq {
+            continue;
+        }
+        let derived = stats.from_input || stats.values.iter().map(|values| values.0).collect::<BTreeSet<u32>>().len() > 1;
+        if !derived {
+            continue;
+        }
+        if tunnels_cmps.iter().any(|tunnel| tunnel.addr == site.addr) {
+            continue;
+        }
+        // Value for the compared register to satisfy the comparison
+        let other = match site.operand {
+            CmpOperand::Reg(rm) => format!("R{}", rm),
+            CmpOperand::Imm(imm) => format!("{}", imm),
+        };
+        let (reg, value) = match site.operand {
+            CmpOperand::Reg(0) if site.rn != 0 => (0, format!("R{}", site.rn)),
+            _ => (site.rn, other),
+        };
+
+        out_str.push_str(&format!("        # {:?}\n", site));
+        out_str.push_str(&format!("        #   {} hits in {} inputs, never satisfied{}\n",
+            stats.hits, stats.inputs, if stats.from_input { ", operand found in input" } else { "" }));
+        out_str.push_str("        #   operands:");
+        for values in stats.values.iter().take(4) {
+            out_str.push_str(&format!(" ({:#x}, {:#x})", values.0, values.1));
+        }
+        out_str.push_str("\n");
+        out_str.push_str(&format!("        - addr:     {:#010x}\n", site.addr));
+        if reg == 0 {
+            out_str.push_str(&format!("          r0:       \"{}\"\n", value));
+        } else {
+            out_str.push_str("          regs:\n");
+            out_str.push_str(&format!("              R{}: \"{}\"\n", reg, value));
+        }
+        candidates += 1;
+    }
+    log::info!("Found {} tunnel candidates", candidates);
+    out_str
+}
@@ -1,33 +1,142 @@
 /// Tunneling allows statically or dynamically setting register values at a specific PC value
-/// This especially allows skipping comparisons with magic values or checksums
+/// This especially allows skipping comparisons with magic values or checksums,
+/// signature verification results and hardware wait loops
 
 use libafl_qemu::*;
 use log;
+use std::fmt::{
+    Debug,
+    Formatter,
+};
 
-static mut TUNNELS_CMPS: Vec<(GuestAddr, String)> = vec![];
+/*
+ *  A tunnel applies its actions whenever the PC hits its address:
+ *  - cond:     only apply if a register is (not) equal to a value
+ *  - regs:     set registers to constants or copies of other registers
+ *  - flags:    force the N, Z, C or V flags of the CPSR
+ *  - mem:      write 32-bit values to guest memory
+ *  - jump:     continue at an address or the value of a register (e.g. "LR" to return)
+ *  Values are decimal constants or register names
+ */
+#[derive(Clone, Default)]
+pub struct Tunnel {
+    pub addr:   GuestAddr,
+    pub cond:   Option<(String, bool, String)>,
+    pub regs:   Vec<(String, String)>,
+    pub flags:  Vec<(char, bool)>,
+    pub mem:    Vec<(GuestAddr, u32)>,
+    pub jump:   Option<String>,
+}
+
+#[derive(Copy, Clone)]
+pub enum TunnelValue {
+    Const(u32),
+    Reg(Regs),
+}
+
+impl TunnelValue {
+    /// Decimal constant or register name
+    pub fn parse(value: &str) -> Self {
+        match value.parse::<u32>() {
+            Ok(value) => TunnelValue::Const(value),
+            Err(_) => TunnelValue::Reg(str_reg_to_regs(value)),
+        }
+    }
+
+    fn read(&self, emu: &Emulator) -> u32 {
+        match *self {
+            TunnelValue::Const(value) => value,
+            TunnelValue::Reg(reg) => emu.read_reg(reg).unwrap(),
+        }
+    }
+}
+
+/* Tunnel compiled at setup, passed to its hook as data */
+struct TunnelAction {
+    emu:            *const Emulator,
+    cond:           Option<(Regs, bool, TunnelValue)>,
+    regs:           Vec<(Regs, TunnelValue)>,
+    flags_set:      u32,
+    flags_clear:    u32,
+    mem:            Vec<(GuestAddr, [u8; 4])>,
+    jump:           Option<TunnelValue>,
+}
 
-pub fn add_tunnels_cmp(addr: GuestAddr, r0: &str, emu: &Emulator) {
-    let cmp = (addr, r0.to_string());
-    unsafe { TUNNELS_CMPS.push(cmp); }
-    emu.set_hook(addr, tunnels_cmp_hook, emu as *const _ as u64, false);
+static mut TUNNELS_JUMPED: bool = false;
+
+pub fn add_tunnels_cmp(tunnel: &Tunnel, emu: &Emulator) {
+    let mut action = TunnelAction {
+        emu:            emu as *const _,
+        cond:           tunnel.cond.as_ref().map(|cond| (str_reg_to_regs(&cond.0), cond.1, TunnelValue::parse(&cond.2))),
+        regs:           tunnel.regs.iter().map(|reg| (str_reg_to_regs(&reg.0), TunnelValue::parse(&reg.1))).collect(),
+        flags_set:      0,
+        flags_clear:    0,
+        mem:            tunnel.mem.iter().map(|mem| (mem.0, mem.1.to_le_bytes())).collect(),
+        jump:           tunnel.jump.as_ref().map(|jump| TunnelValue::parse(jump)),
+    };
+    for flag in tunnel.flags.iter() {
+        if flag.1 {
+            action.flags_set |= 1 << str_flag_to_bit(flag.0);
+        } else {
+            action.flags_clear |= 1 << str_flag_to_bit(flag.0);
+        }
+    }
+    log::debug!("Adding tunnels cmp: {:?}", tunnel);
+    // Hooks are never removed, the action lives as long as the emulator
+    let data = Box::into_raw(Box::new(action)) as u64;
+    emu.set_hook(tunnel.addr, tunnels_cmp_hook, data, false);
+}
+
+/// Whether the emulation was stopped by a jumping tunnel (resets the flag)
+pub fn tunnels_jumped() -> bool {
+    unsafe {
+        let jumped = TUNNELS_JUMPED;
+        TUNNELS_JUMPED = false;
+        jumped
+    }
 }
 
 extern "C" fn tunnels_cmp_hook(pc: GuestAddr, data: u64) {
     log::debug!("Tunnels cmp hook: pc={:#x}", pc);
-    let emu = unsafe { (data as *const Emulator).as_ref().unwrap() };
-    for cmp in unsafe { TUNNELS_CMPS.iter() } {
-        if cmp.0 == pc {
-            log::debug!("Found matching tunnels cmp: [{:#x}, {}]", cmp.0, cmp.1);
-            if cmp.1.parse::<GuestAddr>().is_ok(){
-                emu.write_reg(Regs::R0 as i32, cmp.1.parse::<u32>().unwrap()).unwrap();
-                break;
-            } else {
-                let r0: u64 = emu.read_reg(str_reg_to_regs(&cmp.1)).unwrap();
-                emu.write_reg(Regs::R0 as i32, r0).unwrap();
-                break;
-            }
+    let action = unsafe { (data as *const TunnelAction).as_ref().unwrap() };
+    let emu = unsafe { action.emu.as_ref().unwrap() };
+    if let Some(cond) = &action.cond {
+        let reg: u32 = emu.read_reg(cond.0).unwrap();
+        if (reg == cond.2.read(emu)) != cond.1 {
+            return;
         }
     }
+    for reg in action.regs.iter() {
+        emu.write_reg(reg.0, reg.1.read(emu)).unwrap();
+    }
+    if action.flags_set != 0 || action.flags_clear != 0 {
+        let cpsr: u32 = emu.read_reg(Regs::Cpsr).unwrap();
+        emu.write_reg(Regs::Cpsr, (cpsr | action.flags_set) & !action.flags_clear).unwrap();
+    }
+    for mem in action.mem.iter() {
+        unsafe { emu.current_cpu().unwrap().write_mem(mem.0, &mem.1); }
+    }
+    if let Some(jump) = &action.jump {
+        tunnels_jump(emu, jump.read(emu));
+    }
+}
+
+/// Continues at the target from within a hook
+/// The translated block continues at the old PC, so the emulation is stopped to take the jump
+pub fn tunnels_jump(emu: &Emulator, target: GuestAddr) {
+    emu.write_reg(Regs::Pc, target).unwrap();
+    unsafe { TUNNELS_JUMPED = true; }
+    emu.current_cpu().unwrap().trigger_breakpoint();
+}
+
+pub fn str_flag_to_bit(flag: char) -> u32 {
+    match flag {
+        'N'     => return 31,
+        'Z'     => return 30,
+        'C'     => return 29,
+        'V'     => return 28,
+        _       => panic!("Cannot match to valid CPSR flag"),
+    }
 }
 
 pub fn str_reg_to_regs(reg: &str) -> Regs {
@@ -68,3 +177,25 @@ pub fn str_reg_to_regs(reg: &str) -> Regs {
         _       => panic!("Cannot match to valid ARM register"),
     }
 }
+
+impl Debug for Tunnel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        write!(f, "({:#010x}", self.addr)?;
+        if let Some(cond) = &self.cond {
+            write!(f, ",if {}{}{}", cond.0, if cond.1 { "==" } else { "!=" }, cond.2)?;
+        }
+        for reg in self.regs.iter() {
+            write!(f, ",{}={}", reg.0, reg.1)?;
+        }
+        for flag in self.flags.iter() {
+            write!(f, ",{}={}", flag.0, flag.1 as u8)?;
+        }
+        for mem in self.mem.iter() {
+            write!(f, ",[{:#010x}]={:#x}", mem.0, mem.1)?;
+        }
+        if let Some(jump) = &self.jump {
+            write!(f, ",jump={}", jump)?;
+        }
+        write!(f, ")")
+    }
+}
@@ -0,0 +1,412 @@
+/// Memory-access watchpoints with per-range policies
+/// Each watched range declares the access kinds (R/W/X) it watches, the accessors which are
+/// allowed and the action taken on any other access, backed by the read/write/block hooks
+
+use crate::yaml_conf::borrow_global_conf;
+
+use libafl_qemu::*;
+use libafl::prelude::*;
+
+use log;
+use rangemap::RangeMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::fmt::{
+    Debug,
+    Formatter,
+};
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum WatchAccess {
+    Read,
+    Write,
+    Exec,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum WatchAction {
+    // Ends the execution as a crash
+    Objective,
+    Log,
+    // Punches a hole into larger watched ranges
+    Ignore,
+}
+
+/*
+ *  A watched range [begin, end):
+ *  - access:       watched access kinds
+ *  - allowed:      PCs of the accessing instructions which are allowed
+ *  - allowed_fns:  functions [begin, end) which are allowed, also as the caller (LR) of a leaf
+ *                  function like memcpy, or of the watched code for executions
+ */
+#[derive(Clone)]
+pub struct Watchpoint {
+    pub name:           String,
+    pub begin:          GuestAddr,
+    pub end:            GuestAddr,
+    pub access:         Vec<WatchAccess>,
+    pub allowed:        HashSet<GuestAddr>,
+    pub allowed_fns:    Vec<(GuestAddr, GuestAddr)>,
+    pub action:         WatchAction,
+}
+
+#[derive(Copy, Clone)]
+pub struct WatchViolation {
+    pub watch:  usize,
+    pub access: WatchAccess,
+    pub pc:     GuestAddr,
+    pub addr:   GuestAddr,
+}
+
+/* Watchpoints by range for each access kind, overlapping watchpoints share the range */
+static mut WATCH_RANGES: Vec<(WatchAccess, RangeMap<GuestAddr, Vec<usize>>)> = vec![];
+/* PCs of the instrumented loads and stores, indexed by their hook id */
+static mut WATCH_LOAD_PCS: Vec<GuestAddr> = vec![];
+static mut WATCH_STORE_PCS: Vec<GuestAddr> = vec![];
+static mut WATCH_VIOLATION: Option<WatchViolation> = None;
+static mut WATCH_EMULATOR: u64 = 0;
+/* Thumb state bit of the CPSR */
+const CPSR_THUMB: u32 = 1 << 5;
+
+/// Compiles the watchpoints of the config, has to be called before the hooks are generated
+pub fn watch_init(emu: &Emulator) {
+    let watch = &borrow_global_conf().unwrap().crashes_watch;
+    log::info!("Adding {} watchpoints", watch.len());
+    let mut ranges = vec![];
+    for access in [WatchAccess::Read, WatchAccess::Write, WatchAccess::Exec] {
+        let watched: Vec<usize> = (0..watch.len()).filter(|i| watch[*i].access.contains(&access)).collect();
+        // Split into ranges which are covered by the same watchpoints
+        let mut bounds: Vec<GuestAddr> = watched.iter()
+            .flat_map(|i| [watch[*i].begin, watch[*i].end])
+            .collect();
+        bounds.sort();
+        bounds.dedup();
+        let mut map = RangeMap::new();
+        for bound in bounds.windows(2) {
+            let covering: Vec<usize> = watched.iter()
+                .filter(|i| watch[**i].begin <= bound[0] && bound[1] <= watch[**i].end)
+                .copied()
+                .collect();
+            if !covering.is_empty() {
+                map.insert(bound[0]..bound[1], covering);
+            }
+        }
+        ranges.push((access, map));
+    }
+    unsafe {
+        WATCH_RANGES = ranges;
+        WATCH_EMULATOR = emu as *const _ as u64;
+    }
+}
+
+/// Has to be called before each execution
+pub fn watch_reset() {
+    unsafe { WATCH_VIOLATION = None; }
+}
+
+pub fn watch_violation() -> Option<WatchViolation> {
+    unsafe { WATCH_VIOLATION }
+}
+
+pub fn is_watched(access: WatchAccess) -> bool {
+    borrow_global_conf().unwrap().crashes_watch.iter().any(|watch| watch.access.contains(&access))
+}
+
+fn ranges(access: WatchAccess) -> &'static RangeMap<GuestAddr, Vec<usize>> {
+    unsafe { &WATCH_RANGES.iter().find(|ranges| ranges.0 == access).unwrap().1 }
+}
+
+fn is_allowed(watch: &Watchpoint, pc: GuestAddr, lr: GuestAddr) -> bool {
+    watch.allowed.contains(&pc) ||
+        watch.allowed_fns.iter().any(|func| (func.0..func.1).contains(&pc) || (func.0..func.1).contains(&lr))
+}
+
+/// Watchpoint deciding an access, the smallest watched range overlapping it
+pub fn watch_lookup(access: WatchAccess, addr: GuestAddr, size: usize) -> Option<usize> {
+    let conf = borrow_global_conf().unwrap();
+    ranges(access)
+        .overlapping(&(addr..addr.saturating_add(size as GuestAddr)))
+        .flat_map(|(_, watched)| watched.iter())
+        .min_by_key(|i| conf.crashes_watch[**i].end - conf.crashes_watch[**i].begin)
+        .copied()
+}
+
+fn check(access: WatchAccess, pc: GuestAddr, addr: GuestAddr, size: usize) {
+    let conf = borrow_global_conf().unwrap();
+    let i = match watch_lookup(access, addr, size) {
+        Some(i) => i,
+        None => return,
+    };
+    let watch = &conf.crashes_watch[i];
+    if watch.action == WatchAction::Ignore {
+        return;
+    }
+    let emu = unsafe { (WATCH_EMULATOR as *const Emulator).as_ref().unwrap() };
+    let lr: GuestAddr = emu.read_reg(Regs::Lr).unwrap();
+    if is_allowed(watch, pc, lr) {
+        return;
+    }
+    let violation = WatchViolation { watch: i, access, pc, addr };
+    match watch.action {
+        WatchAction::Objective => {
+            log::debug!("Watchpoint: {:?}", violation);
+            unsafe {
+                if WATCH_VIOLATION.is_none() {
+                    WATCH_VIOLATION = Some(violation);
+                }
+            }
+            emu.current_cpu().unwrap().trigger_breakpoint();
+        },
+        WatchAction::Log => log::info!("Watchpoint: {:?}", violation),
+        WatchAction::Ignore => (),
+    }
+}
+
+/// Whether the block at pc has to be hooked for executions
+pub fn watch_gen_block(pc: GuestAddr) -> bool {
+    ranges(WatchAccess::Exec).get(&pc).is_some()
+}
+
+pub fn watch_exec(pc: GuestAddr) {
+    check(WatchAccess::Exec, pc, pc, 4);
+}
+
+/*
+ *  Access with an address known at translation time: (access, address, size)
+ *  Only PC-relative accesses with an immediate offset in ARM mode are, the base register of
+ *  all other accesses is only known at runtime:
+ *  - LDR/LDRB/STR/STRB Rt, [PC, #+/-imm12]
+ *  - LDRH/LDRSB/LDRSH/LDRD/STRH/STRD Rt, [PC, #+/-imm8]
+ */
+fn pc_relative_access(insn: u32, pc: GuestAddr) -> Option<(WatchAccess, GuestAddr, usize)> {
+    // Unconditional/special instructions
+    if insn >> 28 == 0xf {
+        return None;
+    }
+    let pre_indexed = (insn >> 24) & 1 == 1;
+    let up = (insn >> 23) & 1 == 1;
+    let writeback = (insn >> 21) & 1 == 1;
+    let load = (insn >> 20) & 1 == 1;
+    let rn = (insn >> 16) & 0xf;
+    if rn != 15 || !pre_indexed || writeback {
+        return None;
+    }
+    let (access, offset, size) = if (insn & 0x0e00_0000) == 0x0400_0000 {
+        // Word or byte with a 12-bit immediate
+        let size = if (insn >> 22) & 1 == 1 { 1 } else { 4 };
+        (if load { WatchAccess::Read } else { WatchAccess::Write }, insn & 0xfff, size)
+    } else if (insn & 0x0e40_0090) == 0x0040_0090 && (insn >> 5) & 0x3 != 0 {
+        // Halfword, signed byte or doubleword with an 8-bit immediate
+        let offset = ((insn >> 4) & 0xf0) | (insn & 0xf);
+        match ((insn >> 5) & 0x3, load) {
+            (1, false)  => (WatchAccess::Write, offset, 2),
+            (1, true)   => (WatchAccess::Read, offset, 2),
+            (2, false)  => (WatchAccess::Read, offset, 8),
+            (2, true)   => (WatchAccess::Read, offset, 1),
+            (3, false)  => (WatchAccess::Write, offset, 8),
+            _           => (WatchAccess::Read, offset, 2),
+        }
+    } else {
+        return None;
+    };
+    let base = pc.wrapping_add(8);
+    let addr = if up { base.wrapping_add(offset) } else { base.wrapping_sub(offset) };
+    Some((access, addr, size))
+}
+
+/*
+ *  Whether the access at pc might hit a watchpoint, an access can't if
+ *  - it is allowed by all watchpoints of its kind
+ *  - its address is known at translation time and outside all watched ranges of its kind
+ *  Accesses relative to any other register (including SP, which isn't bound to the stack
+ *  region at runtime) are always instrumented
+ */
+pub fn watch_reaches(access: WatchAccess, pc: GuestAddr) -> bool {
+    let conf = borrow_global_conf().unwrap();
+    let allowed = conf.crashes_watch.iter()
+        .filter(|watch| watch.access.contains(&access) && watch.action != WatchAction::Ignore)
+        .all(|watch| watch.allowed.contains(&pc));
+    if allowed {
+        return false;
+    }
+    let emu = unsafe { (WATCH_EMULATOR as *const Emulator).as_ref().unwrap() };
+    // The CPU is in the mode of the translated code
+    let cpsr: u32 = emu.read_reg(Regs::Cpsr).unwrap();
+    if cpsr & CPSR_THUMB != 0 {
+        return true;
+    }
+    let mut insn = [0u8; 4];
+    unsafe { emu.current_cpu().unwrap().read_mem(pc, &mut insn); }
+    match pc_relative_access(u32::from_le_bytes(insn), pc) {
+        Some((insn_access, addr, size)) if insn_access == access => {
+            ranges(access).overlapping(&(addr..addr.saturating_add(size as GuestAddr))).next().is_some()
+        },
+        _ => true,
+    }
+}
+
+/// Registers a load instrumented by the read hooks
+pub fn watch_gen_load(id: u64, pc: GuestAddr) {
+    unsafe {
+        if WATCH_LOAD_PCS.len() <= id as usize {
+            WATCH_LOAD_PCS.resize(id as usize + 1, 0);
+        }
+        WATCH_LOAD_PCS[id as usize] = pc;
+    }
+}
+
+/// Registers a store instrumented by the write hooks
+pub fn watch_gen_store(id: u64, pc: GuestAddr) {
+    unsafe {
+        if WATCH_STORE_PCS.len() <= id as usize {
+            WATCH_STORE_PCS.resize(id as usize + 1, 0);
+        }
+        WATCH_STORE_PCS[id as usize] = pc;
+    }
+}
+
+pub fn watch_load(id: u64, addr: GuestAddr, size: usize) {
+    let pc = unsafe { WATCH_LOAD_PCS[id as usize] };
+    check(WatchAccess::Read, pc, addr, size);
+}
+
+pub fn watch_store(id: u64, addr: GuestAddr, size: usize) {
+    let pc = unsafe { WATCH_STORE_PCS[id as usize] };
+    check(WatchAccess::Write, pc, addr, size);
+}
+
+/// Metadata of a watchpoint violation
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WatchMetadata {
+    pub violation: String,
+}
+
+impl_serdeany!(WatchMetadata);
+
+/// Access kinds from a string like "RW"
+pub fn str_to_watch_access(input: &str) -> Option<Vec<WatchAccess>> {
+    let mut access = vec![];
+    for kind in input.chars() {
+        let kind = match kind.to_ascii_uppercase() {
+            'R' => WatchAccess::Read,
+            'W' => WatchAccess::Write,
+            'X' => WatchAccess::Exec,
+            _   => return None,
+        };
+        if !access.contains(&kind) {
+            access.push(kind);
+        }
+    }
+    if access.is_empty() {
+        return None;
+    }
+    Some(access)
+}
+
+impl Debug for WatchAccess {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match *self {
+            WatchAccess::Read   => write!(f, "R"),
+            WatchAccess::Write  => write!(f, "W"),
+            WatchAccess::Exec   => write!(f, "X"),
+        }
+    }
+}
+
+impl Debug for WatchAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        match *self {
+            WatchAction::Objective  => write!(f, "objective"),
+            WatchAction::Log        => write!(f, "log"),
+            WatchAction::Ignore     => write!(f, "ignore"),
+        }
+    }
+}
+
+impl FromStr for WatchAction {
+    type Err = ();
+    fn from_str(input: &str) -> Result<WatchAction, ()> {
+        match input {
+            "objective" => Ok(WatchAction::Objective),
+            "log"       => Ok(WatchAction::Log),
+            "ignore"    => Ok(WatchAction::Ignore),
+            _           => Err(()),
+        }
+    }
+}
+
+impl Debug for Watchpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        write!(f, "({},[{:#010x},{:#010x}),", self.name, self.begin, self.end)?;
+        for access in self.access.iter() {
+            write!(f, "{:?}", access)?;
+        }
+        write!(f, ",{:?}", self.action)?;
+        let mut allowed: Vec<&GuestAddr> = self.allowed.iter().collect();
+        allowed.sort();
+        for pc in allowed.into_iter() {
+            write!(f, ",allow {:#010x}", pc)?;
+        }
+        for func in self.allowed_fns.iter() {
+            write!(f, ",allow [{:#010x},{:#010x})", func.0, func.1)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl Debug for WatchViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(),std::fmt::Error> {
+        let conf = borrow_global_conf().unwrap();
+        let watch = &conf.crashes_watch[self.watch];
+        write!(f, "{:#010x}: {:?} access at {:#010x} to \"{}\" [{:#010x}, {:#010x})",
+            self.pc, self.access, self.addr, watch.name, watch.begin, watch.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PC: GuestAddr = 0xffff_1000;
+
+    #[test]
+    fn word_and_byte_literals() {
+        // str r0, [pc, #4]
+        assert!(matches!(pc_relative_access(0xe58f_0004, PC), Some((WatchAccess::Write, 0xffff_100c, 4))));
+        // ldr r0, [pc, #-8]
+        assert!(matches!(pc_relative_access(0xe51f_0008, PC), Some((WatchAccess::Read, 0xffff_1000, 4))));
+        // strb r0, [pc, #0]
+        assert!(matches!(pc_relative_access(0xe5cf_0000, PC), Some((WatchAccess::Write, 0xffff_1008, 1))));
+    }
+
+    #[test]
+    fn halfword_and_doubleword_literals() {
+        // strh r1, [pc, #2]
+        assert!(matches!(pc_relative_access(0xe1cf_10b2, PC), Some((WatchAccess::Write, 0xffff_100a, 2))));
+        // strd r2, r3, [pc, #8]
+        assert!(matches!(pc_relative_access(0xe1cf_20f8, PC), Some((WatchAccess::Write, 0xffff_1010, 8))));
+        // ldrd r2, r3, [pc, #8]
+        assert!(matches!(pc_relative_access(0xe1cf_20d8, PC), Some((WatchAccess::Read, 0xffff_1010, 8))));
+        // ldrsb r1, [pc, #-16]
+        assert!(matches!(pc_relative_access(0xe15f_11d0, PC), Some((WatchAccess::Read, 0xffff_0ff8, 1))));
+    }
+
+    #[test]
+    fn runtime_addresses() {
+        // str r0, [r1, #4]
+        assert!(pc_relative_access(0xe581_0004, PC).is_none());
+        // str r0, [sp, #-4]!
+        assert!(pc_relative_access(0xe52d_0004, PC).is_none());
+        // str r0, [pc, r1]
+        assert!(pc_relative_access(0xe78f_0001, PC).is_none());
+        // str r0, [pc], #4
+        assert!(pc_relative_access(0xe48f_0004, PC).is_none());
+        // strh r1, [pc, r2]
+        assert!(pc_relative_access(0xe18f_10b2, PC).is_none());
+        // mul r0, r1, r2 shares the bits of the extra load/store encodings
+        assert!(pc_relative_access(0xe000_0291, PC).is_none());
+        // Unconditional
+        assert!(pc_relative_access(0xf58f_0004, PC).is_none());
+    }
+}
@@ -2,14 +2,59 @@
 
 use libafl_qemu::*;
 use crate::reset_state::ResetLevel;
+use crate::fixups::{
+    Fixup,
+    FixupKind,
+};
+use crate::ccp_fast_path::{
+    CcpMode,
+    CcpVerify,
+};
+use crate::coverage::CoverageRange;
+use crate::copy_fns::{
+    CopyFn,
+    CopyLen,
+    CopyProtected,
+};
+use crate::dma_writes::DmaDevice;
+use crate::sanitizer::SanitizerBuffer;
+use crate::schedulers::{
+    str_to_power_schedule,
+    SchedulerKind,
+};
+use crate::watchpoints::{
+    str_to_watch_access,
+    WatchAccess,
+    WatchAction,
+    Watchpoint,
+};
+use crate::stubs::{
+    stub_model,
+    Stub,
+    StubReturn,
+};
+use crate::tunneling::{
+    str_flag_to_bit,
+    str_reg_to_regs,
+    Tunnel,
+};
+use crate::input_layout::{
+    Endian,
+    InputLayout,
+    LayoutField,
+};
 
+use std::collections::HashSet;
 use std::fs::File;
 use std::io::Read;
 use std::str::FromStr;
 use std::fmt::{Result, Formatter, Debug};
 
 extern crate yaml_rust;
-use yaml_rust::YamlLoader;
+use yaml_rust::{
+    Yaml,
+    YamlLoader,
+};
 
 static mut CONF: YAMLConfig = YAMLConfig {
     config_file:                    String::new(),
@@ -24,14 +69,33 @@ static mut CONF: YAMLConfig = YAMLConfig {
     input_mem:                      vec![],
     input_fixed:                    vec![],
     input_total_size:               0,
+    input_layout:                   InputLayout { fields: vec![] },
+    input_fixups:                   vec![],
     harness_start:                  0,
     harness_sinks:                  vec![],
+    harness_timeout:                0,
+    harness_block_budget:           0,
+    harness_loop_threshold:         0,
+    harness_wait_masked:            false,
     tunnels_cmps:                   vec![],
+    stubs:                          vec![],
+    ccp_mode:                       CcpMode::Real,
+    ccp_verify:                     vec![],
+    tokens_on_chip_bl:              false,
+    tokens_extra:                   vec![],
+    cmplog_enabled:                 false,
+    scheduler:                      SchedulerKind::Queue,
+    coverage_symbols:               None,
+    coverage_allow:                 vec![],
+    coverage_deny:                  vec![],
+    drcov_modules:                  vec![],
     crashes_breakpoints:            vec![],
-    crashes_mmap_no_exec:           vec![],
-    crashes_mmap_flash_read_fn:     0,
-    crashes_mmap_no_write_flash_fn: vec![],
-    crashes_mmap_no_write_hooks:    vec![],
+    crashes_copy_fns:               vec![],
+    crashes_watch:                  vec![],
+    crashes_shadow_stack:           false,
+    crashes_dma:                    vec![],
+    sanitizer_enabled:              false,
+    sanitizer_buffers:              vec![],
     snapshot_default:               ResetLevel::Lazy,
     snapshot_on_crash:              ResetLevel::Lazy,
     snapshot_periodically:          ResetLevel::Lazy,
@@ -53,14 +117,33 @@ pub struct YAMLConfig {
     pub input_mem:                      Vec<(GuestAddr, usize)>,
     pub input_fixed:                    Vec<(GuestAddr, GuestAddr)>,
     pub input_total_size:               usize,
+    pub input_layout:                   InputLayout,
+    pub input_fixups:                   Vec<Fixup>,
     pub harness_start:                  GuestAddr,
     pub harness_sinks:                  Vec<GuestAddr>,
-    pub tunnels_cmps:                   Vec<(GuestAddr, String)>,
+    pub harness_timeout:                u64,
+    pub harness_block_budget:           u64,
+    pub harness_loop_threshold:         u64,
+    pub harness_wait_masked:            bool,
+    pub tunnels_cmps:                   Vec<Tunnel>,
+    pub stubs:                          Vec<Stub>,
+    pub ccp_mode:                       CcpMode,
+    pub ccp_verify:                     Vec<CcpVerify>,
+    pub tokens_on_chip_bl:              bool,
+    pub tokens_extra:                   Vec<u32>,
+    pub cmplog_enabled:                 bool,
+    pub scheduler:                      SchedulerKind,
+    pub coverage_symbols:               Option<String>,
+    pub coverage_allow:                 Vec<CoverageRange>,
+    pub coverage_deny:                  Vec<CoverageRange>,
+    pub drcov_modules:                  Vec<(String, GuestAddr, GuestAddr)>,
     pub crashes_breakpoints:            Vec<GuestAddr>,
-    pub crashes_mmap_no_exec:           Vec<[GuestAddr; 2]>,
-    pub crashes_mmap_flash_read_fn:     GuestAddr,
-    pub crashes_mmap_no_write_flash_fn: Vec<(GuestAddr, GuestAddr, Vec<GuestAddr>)>,
-    pub crashes_mmap_no_write_hooks:    Vec<(GuestAddr, GuestAddr, Vec<GuestAddr>)>,
+    pub crashes_copy_fns:               Vec<CopyFn>,
+    pub crashes_watch:                  Vec<Watchpoint>,
+    pub crashes_shadow_stack:           bool,
+    pub crashes_dma:                    Vec<DmaDevice>,
+    pub sanitizer_enabled:              bool,
+    pub sanitizer_buffers:              Vec<SanitizerBuffer>,
     pub snapshot_default:               ResetLevel,
     pub snapshot_on_crash:              ResetLevel,
     pub snapshot_periodically:          ResetLevel,
@@ -84,7 +167,7 @@ pub fn borrow_global_conf() -> Option<&'static YAMLConfig> {
 }
 
 impl YAMLConfig {
-    fn new(config_file: &str) -> Self {
+    pub fn new(config_file: &str) -> Self {
         let mut file = File::options()
             .read(true)
             .write(false)
@@ -126,14 +209,14 @@ impl YAMLConfig {
 
         let harness_start = conf["harness"]["start"].as_i64().expect("Expecting 'harness: start:' in yaml") as GuestAddr;
         let harness_sinks_iter = conf["harness"]["sinks"].as_vec().expect("Expecting 'harness: sinks:' in yaml").iter();
+        let harness_timeout = conf["harness"]["timeout"].as_i64().unwrap_or(5000) as u64;
+        let harness_block_budget = conf["harness"]["block_budget"].as_i64().unwrap_or(0) as u64;
+        let harness_loop_threshold = conf["harness"]["loop_threshold"].as_i64().unwrap_or(0) as u64;
+        let harness_wait_masked = conf["harness"]["wait_masked"].as_bool().unwrap_or(false);
 
         let tunnels_cmps_iter = conf["tunnels"]["cmps"].as_vec().expect("Expecting 'tunnels: cmps:' in yaml").iter();
 
         let crashes_breakpoints_iter = conf["crashes"]["breakpoints"].as_vec().expect("Expecting 'crashes: breakpoints:' in yaml").iter();
-        let crashes_mmap_flash_read_fn = conf["crashes"]["mmap"]["flash_read_fn"].as_i64().expect("Expecting 'crashes: mmap: flash_read_fn:' in yaml") as GuestAddr;
-        let crashes_mmap_no_exec_iter = conf["crashes"]["mmap"]["no_exec"].as_vec().expect("Expecting 'crashes: mmap: no_exec:' in yaml").iter();
-        let crashes_mmap_no_write_flash_fn_iter = conf["crashes"]["mmap"]["no_write_flash_fn"].as_vec().expect("Expecting 'crashes: mmap: no_write:' in yaml").iter();
-        let crashes_mmap_no_write_hooks_iter = conf["crashes"]["mmap"]["no_write_hooks"].as_vec().expect("Expecting 'crashes: mmap: no_write:' in yaml").iter();
 
         let mut input_initial = vec![];
         for initial in input_initial_iter {
@@ -145,6 +228,7 @@ impl YAMLConfig {
 
         let mut input_mem = vec![];
         let mut input_total_size = 0;
+        let mut input_layout = InputLayout::default();
         for mem in input_mem_iter {
             if mem["addr"].is_null() || mem["size"].is_null() {
                 break;
@@ -153,8 +237,46 @@ impl YAMLConfig {
                 mem["addr"].as_i64().unwrap() as GuestAddr,
                 mem["size"].as_i64().unwrap() as usize
             ));
+            if let Some(layout_vec) = mem["layout"].as_vec() {
+                for field in layout_vec.iter() {
+                    if field["name"].is_null() || field["offset"].is_null() || field["width"].is_null() {
+                        break;
+                    }
+                    let offset = field["offset"].as_i64().unwrap() as usize;
+                    let width = field["width"].as_i64().unwrap() as usize;
+                    assert!(offset + width <= mem["size"].as_i64().unwrap() as usize, "Layout field outside of input memory region");
+                    let mut values = vec![];
+                    if let Some(values_vec) = field["enum"].as_vec() {
+                        for value in values_vec.iter() {
+                            values.push(value.as_i64().expect("Expecting integers in 'input: mem: layout: enum:' in yaml") as u64);
+                        }
+                    }
+                    let range = field["range"].as_vec().map(|range| (
+                        range[0].as_i64().expect("Expecting [min, max] in 'input: mem: layout: range:' in yaml") as u64,
+                        range[1].as_i64().expect("Expecting [min, max] in 'input: mem: layout: range:' in yaml") as u64,
+                    ));
+                    let length_of = field["length_of"].as_str().map(|target| (
+                        target.to_string(),
+                        field["unit"].as_i64().unwrap_or(1) as usize,
+                    ));
+                    input_layout.fields.push(LayoutField {
+                        name:       field["name"].as_str().unwrap().to_string(),
+                        offset:     input_total_size + offset,
+                        width:      width,
+                        endian:     Endian::from_str(field["endian"].as_str().unwrap_or("little")).expect("Unknown 'input: mem: layout: endian:' in yaml"),
+                        values:     values,
+                        range:      range,
+                        length_of:  length_of,
+                    });
+                }
+            }
             input_total_size += mem["size"].as_i64().unwrap() as usize;
         }
+        for field in input_layout.fields.iter() {
+            if let Some(length_of) = &field.length_of {
+                assert!(input_layout.field(&length_of.0).is_some(), "Unknown layout field {} in 'length_of'", length_of.0);
+            }
+        }
 
         let mut input_fixed = vec![];
         for fixed in input_fixed_iter {
@@ -167,25 +289,180 @@ impl YAMLConfig {
             ));
         }
 
+        let mut input_fixups = vec![];
+        if let Some(input_fixups_vec) = conf["input"]["fixups"].as_vec() {
+            for fixup in input_fixups_vec.iter() {
+                if fixup["kind"].is_null() || fixup["begin"].is_null() || fixup["dest"].is_null() {
+                    break;
+                }
+                input_fixups.push(Fixup {
+                    kind:   FixupKind::from_str(fixup["kind"].as_str().unwrap()).expect("Unknown 'input: fixups: kind:' in yaml"),
+                    begin:  fixup["begin"].as_i64().unwrap() as GuestAddr,
+                    size:   fixup["size"].as_i64().unwrap_or(0) as usize,
+                    count:  fixup["count"].as_i64().map(|count| count as GuestAddr),
+                    stride: fixup["stride"].as_i64().unwrap_or(0) as usize,
+                    dest:   fixup["dest"].as_i64().unwrap() as GuestAddr,
+                });
+            }
+        }
+
         let mut harness_sinks = vec![];
         for sink in harness_sinks_iter {
             harness_sinks.push(sink.as_i64().expect("Expecting at least 1 sink") as GuestAddr);
         }
 
-        let mut tunnels_cmps: Vec<(GuestAddr, String)> = vec![];
+        let mut tunnels_cmps: Vec<Tunnel> = vec![];
         for cmps in tunnels_cmps_iter {
-            if cmps["addr"].is_null() || cmps["r0"].is_null() {
+            if cmps["addr"].is_null() {
                 break;
             }
-            if cmps["r0"].as_str() != None {
-                tunnels_cmps.push((
-                    cmps["addr"].as_i64().unwrap() as GuestAddr,
-                    cmps["r0"].as_str().unwrap().to_string()
-                ));
-            } else {
-                tunnels_cmps.push((
-                    cmps["addr"].as_i64().unwrap() as GuestAddr,
-                    cmps["r0"].as_i64().unwrap().to_string()
+            let mut tunnel = Tunnel {
+                addr: cmps["addr"].as_i64().unwrap() as GuestAddr,
+                ..Default::default()
+            };
+            if cmps["cond"].as_hash().is_some() {
+                let reg = yaml_reg(&cmps["cond"]["reg"]).expect("Expecting 'tunnels: cmps: cond: reg:' in yaml");
+                tunnel.cond = match (yaml_value(&cmps["cond"]["eq"]), yaml_value(&cmps["cond"]["ne"])) {
+                    (Some(value), None) => Some((reg, true, value)),
+                    (None, Some(value)) => Some((reg, false, value)),
+                    _ => panic!("Expecting either 'tunnels: cmps: cond: eq:' or 'ne:' in yaml"),
+                };
+            }
+            // "r0" is a shortcut for "regs: R0:"
+            if let Some(value) = yaml_value(&cmps["r0"]) {
+                tunnel.regs.push(("R0".to_string(), value));
+            }
+            if let Some(regs) = cmps["regs"].as_hash() {
+                for (reg, value) in regs.iter() {
+                    tunnel.regs.push((
+                        yaml_reg(reg).expect("Expecting register names in 'tunnels: cmps: regs:' in yaml"),
+                        yaml_value(value).expect("Expecting values in 'tunnels: cmps: regs:' in yaml"),
+                    ));
+                }
+            }
+            if let Some(flags) = cmps["flags"].as_hash() {
+                for (flag, value) in flags.iter() {
+                    let flag = flag.as_str().expect("Expecting N, Z, C or V in 'tunnels: cmps: flags:' in yaml");
+                    let flag = flag.chars().next().unwrap_or(' ');
+                    str_flag_to_bit(flag);
+                    let set = value.as_bool().or(value.as_i64().map(|value| value != 0))
+                        .expect("Expecting 0 or 1 in 'tunnels: cmps: flags:' in yaml");
+                    tunnel.flags.push((flag, set));
+                }
+            }
+            if let Some(mem) = cmps["mem"].as_vec() {
+                for write in mem.iter() {
+                    if write["addr"].is_null() || write["val"].is_null() {
+                        break;
+                    }
+                    tunnel.mem.push((
+                        write["addr"].as_i64().unwrap() as GuestAddr,
+                        write["val"].as_i64().unwrap() as u32,
+                    ));
+                }
+            }
+            tunnel.jump = yaml_value(&cmps["jump"]);
+            if tunnel.regs.is_empty() && tunnel.flags.is_empty() && tunnel.mem.is_empty() && tunnel.jump.is_none() {
+                break;
+            }
+            tunnels_cmps.push(tunnel);
+        }
+
+        // Stubs are optional
+        let mut stubs = vec![];
+        if let Some(stubs_vec) = conf["stubs"].as_vec() {
+            for stub in stubs_vec.iter() {
+                if stub["addr"].is_null() {
+                    break;
+                }
+                let ret = if let Some(ret) = stub["ret"].as_i64() {
+                    StubReturn::Value(ret as u32)
+                } else if let Some(arg) = stub["ret_arg"].as_i64() {
+                    StubReturn::Arg(arg as usize)
+                } else if let Some(model) = stub["model"].as_str() {
+                    stub_model(model).expect("Expecting a valid 'stubs: model:' in yaml");
+                    StubReturn::Model(model.to_string())
+                } else {
+                    StubReturn::Void
+                };
+                let buf = if stub["buf"].as_hash().is_some() {
+                    let arg = stub["buf"]["arg"].as_i64().expect("Expecting 'stubs: buf: arg:' in yaml") as usize;
+                    let data = stub["buf"]["data"].as_vec().expect("Expecting 'stubs: buf: data:' in yaml")
+                        .iter()
+                        .map(|byte| byte.as_i64().expect("Expecting bytes in 'stubs: buf: data:' in yaml") as u8)
+                        .collect();
+                    Some((arg, data))
+                } else {
+                    None
+                };
+                stubs.push(Stub {
+                    addr:   stub["addr"].as_i64().unwrap() as GuestAddr,
+                    buf:    buf,
+                    ret:    ret,
+                });
+            }
+        }
+
+        // CCP fast-path is optional
+        let ccp_mode = CcpMode::from_str(conf["ccp"]["mode"].as_str().unwrap_or("real")).expect("Unknown 'ccp: mode:' in yaml");
+        let mut ccp_verify = vec![];
+        if let Some(ccp_verify_vec) = conf["ccp"]["verify"].as_vec() {
+            for verify in ccp_verify_vec.iter() {
+                if verify["addr"].is_null() {
+                    break;
+                }
+                ccp_verify.push(CcpVerify {
+                    addr:       verify["addr"].as_i64().unwrap() as GuestAddr,
+                    valid:      verify["valid"].as_i64().expect("Expecting 'ccp: verify: valid:' in yaml") as u32,
+                    invalid:    verify["invalid"].as_i64().expect("Expecting 'ccp: verify: invalid:' in yaml") as u32,
+                });
+            }
+        }
+
+        // Token dictionary is optional
+        let tokens_on_chip_bl = conf["tokens"]["on_chip_bl"].as_bool().unwrap_or(false);
+        let mut tokens_extra = vec![];
+        if let Some(tokens_extra_vec) = conf["tokens"]["extra"].as_vec() {
+            for token in tokens_extra_vec.iter() {
+                if token.is_null() {
+                    break;
+                }
+                tokens_extra.push(token.as_i64().expect("Expecting integers in 'tokens: extra:' in yaml") as u32);
+            }
+        }
+
+        // CmpLog is optional
+        let cmplog_enabled = conf["cmplog"]["enabled"].as_bool().unwrap_or(false);
+
+        // Scheduler is optional, the plain queue by default
+        let mut scheduler = SchedulerKind::from_str(conf["scheduler"]["kind"].as_str().unwrap_or("queue"))
+            .expect("Unknown 'scheduler: kind:' in yaml, expecting queue, minimizer, power or weighted");
+        if let Some(schedule) = conf["scheduler"]["schedule"].as_str() {
+            let schedule = str_to_power_schedule(schedule)
+                .expect("Unknown 'scheduler: schedule:' in yaml, expecting explore, fast or coe");
+            scheduler = match scheduler {
+                SchedulerKind::Power(_)     => SchedulerKind::Power(schedule),
+                SchedulerKind::Weighted(_)  => SchedulerKind::Weighted(schedule),
+                _                           => panic!("'scheduler: schedule:' needs a power or weighted scheduler in yaml"),
+            };
+        }
+
+        // Coverage filter is optional
+        let coverage_symbols = conf["coverage"]["symbols"].as_str().map(|path| path.to_string());
+        let coverage_allow = yaml_coverage_ranges(&conf["coverage"]["allow"]);
+        let coverage_deny = yaml_coverage_ranges(&conf["coverage"]["deny"]);
+
+        // DrCov modules next to the on-chip bootloader are optional
+        let mut drcov_modules = vec![];
+        if let Some(modules_vec) = conf["drcov"]["modules"].as_vec() {
+            for module in modules_vec.iter() {
+                if module["name"].is_null() || module["begin"].is_null() || module["end"].is_null() {
+                    break;
+                }
+                drcov_modules.push((
+                    module["name"].as_str().expect("Expecting a string in 'drcov: modules: name:' in yaml").to_string(),
+                    module["begin"].as_i64().unwrap() as GuestAddr,
+                    module["end"].as_i64().unwrap() as GuestAddr,
                 ));
             }
         }
@@ -198,53 +475,186 @@ impl YAMLConfig {
             crashes_breakpoints.push(breakpoint.as_i64().unwrap() as GuestAddr);
         }
 
-        let mut crashes_mmap_no_exec = vec![];
-        for no_exec in crashes_mmap_no_exec_iter {
-            if no_exec["begin"].is_null() || no_exec["end"].is_null() {
-                break;
+        // Watchpoints, "no_exec" and "no_write_hooks" are watchpoints of all accessors
+        let mut crashes_watch = vec![];
+        if let Some(no_exec_vec) = conf["crashes"]["mmap"]["no_exec"].as_vec() {
+            for no_exec in no_exec_vec.iter() {
+                if no_exec["begin"].is_null() || no_exec["end"].is_null() {
+                    break;
+                }
+                crashes_watch.push(Watchpoint {
+                    name:           "no_exec".to_string(),
+                    begin:          no_exec["begin"].as_i64().unwrap() as GuestAddr,
+                    end:            no_exec["end"].as_i64().unwrap() as GuestAddr,
+                    access:         vec![WatchAccess::Exec],
+                    allowed:        HashSet::new(),
+                    allowed_fns:    vec![],
+                    action:         WatchAction::Objective,
+                });
+            }
+        }
+        if let Some(no_write_vec) = conf["crashes"]["mmap"]["no_write_hooks"].as_vec() {
+            for no_write in no_write_vec.iter() {
+                if no_write["begin"].is_null() || no_write["end"].is_null() {
+                    break;
+                }
+                let mut no_ldr_set: HashSet<GuestAddr> = HashSet::new();
+                if !no_write["no_ldr"].is_null() {
+                    let crashes_mmap_no_write_no_ldr_iter = no_write["no_ldr"].as_vec().expect("Expecting 'crashes: mmap: no_write_hooks: no_ldr:' in yaml").iter();
+                    for no_ldr in crashes_mmap_no_write_no_ldr_iter {
+                        no_ldr_set.insert(no_ldr.as_i64().unwrap() as GuestAddr);
+                    }
+                }
+                crashes_watch.push(Watchpoint {
+                    name:           "no_write_hooks".to_string(),
+                    begin:          no_write["begin"].as_i64().unwrap() as GuestAddr,
+                    end:            no_write["end"].as_i64().unwrap() as GuestAddr,
+                    access:         vec![WatchAccess::Write],
+                    allowed:        no_ldr_set,
+                    allowed_fns:    vec![],
+                    action:         WatchAction::Objective,
+                });
+            }
+        }
+        if let Some(watch_vec) = conf["crashes"]["watch"].as_vec() {
+            for watch in watch_vec.iter() {
+                if watch["begin"].is_null() || watch["end"].is_null() {
+                    break;
+                }
+                let begin = watch["begin"].as_i64().unwrap() as GuestAddr;
+                let mut allowed = HashSet::new();
+                if let Some(allowed_vec) = watch["allowed"].as_vec() {
+                    for pc in allowed_vec.iter() {
+                        if pc.is_null() {
+                            break;
+                        }
+                        allowed.insert(pc.as_i64().expect("Expecting PCs in 'crashes: watch: allowed:' in yaml") as GuestAddr);
+                    }
+                }
+                let mut allowed_fns = vec![];
+                if let Some(allowed_fns_vec) = watch["allowed_fns"].as_vec() {
+                    for func in allowed_fns_vec.iter() {
+                        if func["begin"].is_null() || func["end"].is_null() {
+                            break;
+                        }
+                        allowed_fns.push((
+                            func["begin"].as_i64().unwrap() as GuestAddr,
+                            func["end"].as_i64().unwrap() as GuestAddr,
+                        ));
+                    }
+                }
+                crashes_watch.push(Watchpoint {
+                    name:           watch["name"].as_str().map(|name| name.to_string()).unwrap_or(format!("{:#010x}", begin)),
+                    begin:          begin,
+                    end:            watch["end"].as_i64().unwrap() as GuestAddr,
+                    access:         str_to_watch_access(watch["access"].as_str().expect("Expecting 'crashes: watch: access:' in yaml"))
+                                        .expect("Expecting R, W and/or X in 'crashes: watch: access:' in yaml"),
+                    allowed:        allowed,
+                    allowed_fns:    allowed_fns,
+                    action:         WatchAction::from_str(watch["action"].as_str().unwrap_or("objective"))
+                                        .expect("Unknown 'crashes: watch: action:' in yaml"),
+                });
             }
-            crashes_mmap_no_exec.push([
-                no_exec["begin"].as_i64().unwrap() as GuestAddr,
-                no_exec["end"].as_i64().unwrap() as GuestAddr
-            ]);
         }
 
-        let mut crashes_mmap_no_write_hooks = vec![];
-        for no_write in crashes_mmap_no_write_hooks_iter {
-            if no_write["begin"].is_null() || no_write["end"].is_null() {
-                break;
+        // Copy functions, "flash_read_fn" with "no_write_flash_fn" is a copy function (src, dest, len)
+        let mut crashes_copy_fns = vec![];
+        let mut flash_read_protected = vec![];
+        if let Some(no_write_vec) = conf["crashes"]["mmap"]["no_write_flash_fn"].as_vec() {
+            for no_write in no_write_vec.iter() {
+                if no_write["begin"].is_null() || no_write["end"].is_null() {
+                    break;
+                }
+                flash_read_protected.push(CopyProtected {
+                    begin:      no_write["begin"].as_i64().unwrap() as GuestAddr,
+                    end:        no_write["end"].as_i64().unwrap() as GuestAddr,
+                    allowed:    yaml_addrs(&no_write["no_hook"]),
+                });
             }
-            let mut no_ldr_vec: Vec<GuestAddr> = vec![];
-            if !no_write["no_ldr"].is_null() {
-                let crashes_mmap_no_write_no_ldr_iter = no_write["no_ldr"].as_vec().expect("Expecting 'crashes: mmap: no_write_hooks: no_ldr:' in yaml").iter();
-                for no_ldr in crashes_mmap_no_write_no_ldr_iter {
-                    no_ldr_vec.push(no_ldr.as_i64().unwrap() as GuestAddr);
+        }
+        if let (Some(addr), false) = (conf["crashes"]["mmap"]["flash_read_fn"].as_i64(), flash_read_protected.is_empty()) {
+            crashes_copy_fns.push(CopyFn {
+                name:       "flash_read_fn".to_string(),
+                addr:       addr as GuestAddr,
+                src:        Some(0),
+                dest:       1,
+                len:        CopyLen::Arg(2),
+                protected:  flash_read_protected,
+            });
+        }
+        if let Some(copy_fns_vec) = conf["crashes"]["copy_fns"].as_vec() {
+            for copy_fn in copy_fns_vec.iter() {
+                if copy_fn["addr"].is_null() {
+                    break;
+                }
+                let addr = copy_fn["addr"].as_i64().unwrap() as GuestAddr;
+                let len = if let Some(len) = copy_fn["len"].as_i64() {
+                    CopyLen::Arg(len as usize)
+                } else {
+                    CopyLen::Const(copy_fn["size"].as_i64().expect("Expecting 'crashes: copy_fns: len:' or 'size:' in yaml") as u32)
+                };
+                let mut protected = vec![];
+                for region in copy_fn["protected"].as_vec().expect("Expecting 'crashes: copy_fns: protected:' in yaml").iter() {
+                    if region["begin"].is_null() || region["end"].is_null() {
+                        break;
+                    }
+                    protected.push(CopyProtected {
+                        begin:      region["begin"].as_i64().unwrap() as GuestAddr,
+                        end:        region["end"].as_i64().unwrap() as GuestAddr,
+                        allowed:    yaml_addrs(&region["allowed"]),
+                    });
                 }
+                crashes_copy_fns.push(CopyFn {
+                    name:       copy_fn["name"].as_str().map(|name| name.to_string()).unwrap_or(format!("{:#010x}", addr)),
+                    addr:       addr,
+                    src:        copy_fn["src"].as_i64().map(|src| src as usize),
+                    dest:       copy_fn["dest"].as_i64().expect("Expecting 'crashes: copy_fns: dest:' in yaml") as usize,
+                    len:        len,
+                    protected:  protected,
+                });
             }
-            crashes_mmap_no_write_hooks.push((
-                no_write["begin"].as_i64().unwrap() as GuestAddr,
-                no_write["end"].as_i64().unwrap() as GuestAddr,
-                no_ldr_vec
-            ));
         }
 
-        let mut crashes_mmap_no_write_flash_fn = vec![];
-        for no_write in crashes_mmap_no_write_flash_fn_iter {
-            if no_write["begin"].is_null() || no_write["end"].is_null() {
-                break;
+        // Shadow call stack is optional
+        let crashes_shadow_stack = conf["crashes"]["shadow_stack"].as_bool().unwrap_or(false);
+
+        // Checked device writes are optional
+        let mut crashes_dma = vec![];
+        if let Some(dma_vec) = conf["crashes"]["dma"].as_vec() {
+            for device in dma_vec.iter() {
+                if device.is_null() {
+                    break;
+                }
+                crashes_dma.push(DmaDevice::from_str(device.as_str().expect("Expecting device names in 'crashes: dma:' in yaml"))
+                    .expect("Expecting 'ccp' or 'smn' in 'crashes: dma:' in yaml"));
             }
-            let mut no_hook_vec: Vec<GuestAddr> = vec![];
-            if !no_write["no_hook"].is_null() {
-                let crashes_mmap_no_write_flash_fn_iter = no_write["no_hook"].as_vec().expect("Expecting 'crashes: mmap: no_write_flash_fn: no_hook:' in yaml").iter();
-                for no_hook in crashes_mmap_no_write_flash_fn_iter {
-                    no_hook_vec.push(no_hook.as_i64().unwrap() as GuestAddr);
+        }
+
+        // Sanitizer is optional
+        let sanitizer_enabled = conf["sanitizer"]["enabled"].as_bool().unwrap_or(false);
+        let mut sanitizer_buffers = vec![];
+        if let Some(sanitizer_buffers_vec) = conf["sanitizer"]["buffers"].as_vec() {
+            for buffer in sanitizer_buffers_vec.iter() {
+                if buffer["begin"].is_null() || buffer["end"].is_null() {
+                    break;
+                }
+                let begin = buffer["begin"].as_i64().unwrap() as GuestAddr;
+                let end = buffer["end"].as_i64().unwrap() as GuestAddr;
+                if begin >= end || end > qemu_sram_size {
+                    panic!("Expecting 'sanitizer: buffers:' in SRAM with begin < end in yaml");
                 }
+                sanitizer_buffers.push(SanitizerBuffer {
+                    name:   buffer["name"].as_str().map(|name| name.to_string()).unwrap_or(format!("{:#010x}", begin)),
+                    begin:  begin,
+                    end:    end,
+                    uninit: buffer["uninit"].as_bool().unwrap_or(false),
+                });
             }
-            crashes_mmap_no_write_flash_fn.push((
-                no_write["begin"].as_i64().unwrap() as GuestAddr,
-                no_write["end"].as_i64().unwrap() as GuestAddr,
-                no_hook_vec
-            ));
+        }
+        // Buffers are looked up by binary search
+        sanitizer_buffers.sort_by_key(|buffer| buffer.begin);
+        if sanitizer_buffers.windows(2).any(|buffers| buffers[0].end > buffers[1].begin) {
+            panic!("Expecting non-overlapping 'sanitizer: buffers:' in yaml");
         }
 
         Self {
@@ -260,14 +670,33 @@ impl YAMLConfig {
             input_mem:                      input_mem,
             input_fixed:                    input_fixed,
             input_total_size:               input_total_size,
+            input_layout:                   input_layout,
+            input_fixups:                   input_fixups,
             harness_start:                  harness_start,
             harness_sinks:                  harness_sinks,
+            harness_timeout:                harness_timeout,
+            harness_block_budget:           harness_block_budget,
+            harness_loop_threshold:         harness_loop_threshold,
+            harness_wait_masked:            harness_wait_masked,
             tunnels_cmps:                   tunnels_cmps,
+            stubs:                          stubs,
+            ccp_mode:                       ccp_mode,
+            ccp_verify:                     ccp_verify,
+            tokens_on_chip_bl:              tokens_on_chip_bl,
+            tokens_extra:                   tokens_extra,
+            cmplog_enabled:                 cmplog_enabled,
+            scheduler:                      scheduler,
+            coverage_symbols:               coverage_symbols,
+            coverage_allow:                 coverage_allow,
+            coverage_deny:                  coverage_deny,
+            drcov_modules:                  drcov_modules,
             crashes_breakpoints:            crashes_breakpoints,
-            crashes_mmap_no_exec:           crashes_mmap_no_exec,
-            crashes_mmap_flash_read_fn:     crashes_mmap_flash_read_fn,
-            crashes_mmap_no_write_flash_fn: crashes_mmap_no_write_flash_fn,
-            crashes_mmap_no_write_hooks:    crashes_mmap_no_write_hooks,
+            crashes_copy_fns:               crashes_copy_fns,
+            crashes_watch:                  crashes_watch,
+            crashes_shadow_stack:           crashes_shadow_stack,
+            crashes_dma:                    crashes_dma,
+            sanitizer_enabled:              sanitizer_enabled,
+            sanitizer_buffers:              sanitizer_buffers,
             snapshot_default:               ResetLevel::from_str(conf["snapshot"]["default"].as_str().unwrap()).unwrap(),
             snapshot_on_crash:              ResetLevel::from_str(conf["snapshot"]["on_crash"].as_str().unwrap()).unwrap(),
             snapshot_periodically:          ResetLevel::from_str(conf["snapshot"]["periodically"].as_str().unwrap()).unwrap(),
@@ -298,11 +727,25 @@ impl Debug for YAMLConfig {
         }
         out_str.push_str(&format!("]\n"));
         out_str.push_str(&format!("\ttotal size:\t\t\t{:#x}\n", self.input_total_size));
+        out_str.push_str(&format!("\tlayout:\t\t\t\t["));
+        for field in self.input_layout.fields.iter() {
+            out_str.push_str(&format!("{:?}, ", field));
+        }
+        out_str.push_str(&format!("]\n"));
         out_str.push_str(&format!("\tfixed:\t\t\t\t["));
         for fixed in self.input_fixed.iter() {
             out_str.push_str(&format!("({:#010x},{:#x}), ", fixed.0, fixed.1));
         }
         out_str.push_str(&format!("]\n"));
+        out_str.push_str(&format!("\tfixups:\t\t\t\t["));
+        for fixup in self.input_fixups.iter() {
+            out_str.push_str(&format!("({:?},{:#010x},{:#x}", fixup.kind, fixup.begin, fixup.size));
+            if let Some(count) = fixup.count {
+                out_str.push_str(&format!("+[{:#010x}]*{:#x}", count, fixup.stride));
+            }
+            out_str.push_str(&format!("->{:#010x}), ", fixup.dest));
+        }
+        out_str.push_str(&format!("]\n"));
         out_str.push_str(&format!("Harness:\n"));
         out_str.push_str(&format!("\tstart:\t\t\t\t{:#010x}\n", self.harness_start));
         out_str.push_str(&format!("\tsinks:\t\t\t\t["));
@@ -310,10 +753,47 @@ impl Debug for YAMLConfig {
             out_str.push_str(&format!("{:#010x}, ", sink));
         }
         out_str.push_str(&format!("]\n"));
+        out_str.push_str(&format!("\ttimeout:\t\t\t{}ms\n", self.harness_timeout));
+        out_str.push_str(&format!("\tblock budget:\t\t\t{}\n", self.harness_block_budget));
+        out_str.push_str(&format!("\tloop threshold:\t\t\t{}\n", self.harness_loop_threshold));
+        out_str.push_str(&format!("\twait masked:\t\t\t{}\n", self.harness_wait_masked));
         out_str.push_str(&format!("Tunnels:\n"));
         out_str.push_str(&format!("\tcmps:\t\t\t\t["));
         for cmps in self.tunnels_cmps.iter() {
-            out_str.push_str(&format!("({:#010x},R0={}), ", cmps.0, cmps.1));
+            out_str.push_str(&format!("{:?}, ", cmps));
+        }
+        out_str.push_str(&format!("]\n"));
+        out_str.push_str(&format!("Stubs:\t\t\t\t\t["));
+        for stub in self.stubs.iter() {
+            out_str.push_str(&format!("{:?}, ", stub));
+        }
+        out_str.push_str(&format!("]\n"));
+        out_str.push_str(&format!("CCP:\n"));
+        out_str.push_str(&format!("\tmode:\t\t\t\t{:?}\n", self.ccp_mode));
+        out_str.push_str(&format!("\tverify:\t\t\t\t["));
+        for verify in self.ccp_verify.iter() {
+            out_str.push_str(&format!("{:?}, ", verify));
+        }
+        out_str.push_str(&format!("]\n"));
+        out_str.push_str(&format!("Tokens:\n"));
+        out_str.push_str(&format!("\ton-chip bl:\t\t\t{}\n", self.tokens_on_chip_bl));
+        out_str.push_str(&format!("\textra:\t\t\t\t["));
+        for token in self.tokens_extra.iter() {
+            out_str.push_str(&format!("{:#010x}, ", token));
+        }
+        out_str.push_str(&format!("]\n"));
+        out_str.push_str(&format!("CmpLog:\n"));
+        out_str.push_str(&format!("\tenabled:\t\t\t{}\n", self.cmplog_enabled));
+        out_str.push_str(&format!("Scheduler:\n"));
+        out_str.push_str(&format!("\tkind:\t\t\t\t{:?}\n", self.scheduler));
+        out_str.push_str(&format!("Coverage:\n"));
+        out_str.push_str(&format!("\tsymbols:\t\t\t{:?}\n", self.coverage_symbols));
+        out_str.push_str(&format!("\tallow:\t\t\t\t{:?}\n", self.coverage_allow));
+        out_str.push_str(&format!("\tdeny:\t\t\t\t{:?}\n", self.coverage_deny));
+        out_str.push_str(&format!("DrCov:\n"));
+        out_str.push_str(&format!("\tmodules:\t\t\t["));
+        for module in self.drcov_modules.iter() {
+            out_str.push_str(&format!("({},{:#010x},{:#010x}), ", module.0, module.1, module.2));
         }
         out_str.push_str(&format!("]\n"));
         out_str.push_str(&format!("Crashes:\n"));
@@ -322,28 +802,23 @@ impl Debug for YAMLConfig {
             out_str.push_str(&format!("{:#010x}, ", breakpoint));
         }
         out_str.push_str(&format!("]\n"));
-        out_str.push_str(&format!("\tmmap no_exec:\t\t\t["));
-        for no_exec in self.crashes_mmap_no_exec.iter() {
-            out_str.push_str(&format!("({:#010x},{:#010x}), ", no_exec[0], no_exec[1]));
+        out_str.push_str(&format!("\tcopy functions:\t\t\t["));
+        for copy_fn in self.crashes_copy_fns.iter() {
+            out_str.push_str(&format!("{:?}, ", copy_fn));
         }
         out_str.push_str(&format!("]\n"));
-        out_str.push_str(&format!("\tmmap flash read function:\t{:#010x}\n", self.crashes_mmap_flash_read_fn));
-        out_str.push_str(&format!("\tmmap no_write_flash_fn:\t\t["));
-        for no_write in self.crashes_mmap_no_write_flash_fn.iter() {
-            out_str.push_str(&format!("({:#010x},{:#010x}), [", no_write.0, no_write.1));
-            for no_hook in &no_write.2 {
-                out_str.push_str(&format!("{:#010x}, ", no_hook));
-            }
-            out_str.push_str(&format!("]), "));
+        out_str.push_str(&format!("\twatch:\t\t\t\t["));
+        for watch in self.crashes_watch.iter() {
+            out_str.push_str(&format!("{:?}, ", watch));
         }
         out_str.push_str(&format!("]\n"));
-        out_str.push_str(&format!("\tmmap no_write_hooks:\t\t["));
-        for no_write in self.crashes_mmap_no_write_hooks.iter() {
-            out_str.push_str(&format!("({:#010x},{:#010x}, [", no_write.0, no_write.1));
-            for no_ldr in &no_write.2 {
-                out_str.push_str(&format!("{:#010x}, ", no_ldr));
-            }
-            out_str.push_str(&format!("]), "));
+        out_str.push_str(&format!("\tshadow call stack:\t\t{}\n", self.crashes_shadow_stack));
+        out_str.push_str(&format!("\tdevice writes:\t\t\t{:?}\n", self.crashes_dma));
+        out_str.push_str(&format!("Sanitizer:\n"));
+        out_str.push_str(&format!("\tenabled:\t\t\t{}\n", self.sanitizer_enabled));
+        out_str.push_str(&format!("\tbuffers:\t\t\t["));
+        for buffer in self.sanitizer_buffers.iter() {
+            out_str.push_str(&format!("{:?}, ", buffer));
         }
         out_str.push_str(&format!("]\n"));
         out_str.push_str(&format!("Snapshot:\n"));
@@ -354,3 +829,55 @@ impl Debug for YAMLConfig {
         write!(f, "{}", out_str)
     }
 }
+
+/* Tunnel values are decimal constants or valid register names */
+fn yaml_value(value: &Yaml) -> Option<String> {
+    if let Some(value) = value.as_i64() {
+        return Some((value as u32).to_string());
+    }
+    if value.as_str()?.parse::<u32>().is_ok() {
+        return Some(value.as_str().unwrap().to_string());
+    }
+    yaml_reg(value)
+}
+
+/* Coverage ranges with "begin"/"end" or a "symbol", ending at the first null */
+fn yaml_coverage_ranges(ranges: &Yaml) -> Vec<CoverageRange> {
+    let mut out = vec![];
+    if let Some(ranges) = ranges.as_vec() {
+        for range in ranges.iter() {
+            if let Some(symbol) = range["symbol"].as_str() {
+                out.push(CoverageRange::Symbol(symbol.to_string()));
+                continue;
+            }
+            if range["begin"].is_null() || range["end"].is_null() {
+                break;
+            }
+            out.push(CoverageRange::Addr(
+                range["begin"].as_i64().unwrap() as GuestAddr,
+                range["end"].as_i64().unwrap() as GuestAddr,
+            ));
+        }
+    }
+    out
+}
+
+/* List of addresses, empty if missing and ending at the first null */
+fn yaml_addrs(addrs: &Yaml) -> Vec<GuestAddr> {
+    let mut out = vec![];
+    if let Some(addrs) = addrs.as_vec() {
+        for addr in addrs.iter() {
+            if addr.is_null() {
+                break;
+            }
+            out.push(addr.as_i64().expect("Expecting a list of addresses in yaml") as GuestAddr);
+        }
+    }
+    out
+}
+
+fn yaml_reg(reg: &Yaml) -> Option<String> {
+    let reg = reg.as_str()?;
+    str_reg_to_regs(reg);
+    Some(reg.to_string())
+}
@@ -86,21 +86,41 @@ for solutions_file_path in glob.glob(os.path.join(solutions_dir_path,"*")):
 
         mut_flashimg_bytes[fixed_addr:fixed_addr+4] = fixed_val.to_bytes(4, 'little')
 
-    if int.from_bytes(mut_flashimg_bytes[COMBO_DIR_MAGIC_ADDR:COMBO_DIR_MAGIC_ADDR+4], 'little') == COMBO_DIR_MAGIC:
-        # Fletcher checksum 1
-        dir_len_1 = int.from_bytes(mut_flashimg_bytes[COMBO_DIR_LEN_ADDR:COMBO_DIR_LEN_ADDR+4],'little') * 16 + 0x18
-        dir_data_1 = mut_flashimg_bytes[COMBO_DIR_LEN_ADDR:COMBO_DIR_LEN_ADDR+dir_len_1]
-        mut_flashimg_bytes[COMBO_DIR_CHECKSUM_ADDR:COMBO_DIR_CHECKSUM_ADDR+4] = fletcher32(dir_data_1)
-
-        # Fletcher checksum 2
-        dir_len_2 = int.from_bytes(mut_flashimg_bytes[DIR_LEN_ADDR:DIR_LEN_ADDR+4],'little') * 16 + 0x8
-        dir_data_2 = mut_flashimg_bytes[DIR_LEN_ADDR:DIR_LEN_ADDR+dir_len_2]
-        mut_flashimg_bytes[DIR_CHECKSUM_ADDR:DIR_CHECKSUM_ADDR+4] = fletcher32(dir_data_2)
-    else:
-        # Fletcher checksum 1
-        dir_len_1 = int.from_bytes(mut_flashimg_bytes[COMBO_DIR_LEN_ADDR:COMBO_DIR_LEN_ADDR+4],'little') * 16 + 0x8
-        dir_data_1 = mut_flashimg_bytes[COMBO_DIR_LEN_ADDR:COMBO_DIR_LEN_ADDR+dir_len_1]
-        mut_flashimg_bytes[COMBO_DIR_CHECKSUM_ADDR:COMBO_DIR_CHECKSUM_ADDR+4] = fletcher32(dir_data_1)
+    # Recompute checksums with the input fixups like the fuzzer does before writing to flash
+    fixups = yaml_config["input"].get("fixups") or []
+    for fixup in fixups:
+        if fixup["kind"] == None or fixup["begin"] == None or fixup["dest"] == None:
+            break
+        if fixup["kind"] != "fletcher32":
+            print(f"Unknown fixup kind {fixup['kind']}")
+            continue
+
+        fixup_begin = fixup["begin"]
+        fixup_len = fixup.get("size") or 0
+        if fixup.get("count") != None:
+            fixup_count = int.from_bytes(mut_flashimg_bytes[fixup["count"]:fixup["count"]+4], 'little')
+            fixup_len += fixup_count * (fixup.get("stride") or 0)
+        fixup_len = min(fixup_len, len(mut_flashimg_bytes) - fixup_begin)
+        fixup_data = mut_flashimg_bytes[fixup_begin:fixup_begin+fixup_len]
+        mut_flashimg_bytes[fixup["dest"]:fixup["dest"]+4] = fletcher32(fixup_data)
+
+    # Configs without fixups: directory checksums of the base image layout
+    if not fixups:
+        if int.from_bytes(mut_flashimg_bytes[COMBO_DIR_MAGIC_ADDR:COMBO_DIR_MAGIC_ADDR+4], 'little') == COMBO_DIR_MAGIC:
+            # Fletcher checksum 1
+            dir_len_1 = int.from_bytes(mut_flashimg_bytes[COMBO_DIR_LEN_ADDR:COMBO_DIR_LEN_ADDR+4],'little') * 16 + 0x18
+            dir_data_1 = mut_flashimg_bytes[COMBO_DIR_LEN_ADDR:COMBO_DIR_LEN_ADDR+dir_len_1]
+            mut_flashimg_bytes[COMBO_DIR_CHECKSUM_ADDR:COMBO_DIR_CHECKSUM_ADDR+4] = fletcher32(dir_data_1)
+
+            # Fletcher checksum 2
+            dir_len_2 = int.from_bytes(mut_flashimg_bytes[DIR_LEN_ADDR:DIR_LEN_ADDR+4],'little') * 16 + 0x8
+            dir_data_2 = mut_flashimg_bytes[DIR_LEN_ADDR:DIR_LEN_ADDR+dir_len_2]
+            mut_flashimg_bytes[DIR_CHECKSUM_ADDR:DIR_CHECKSUM_ADDR+4] = fletcher32(dir_data_2)
+        else:
+            # Fletcher checksum 1
+            dir_len_1 = int.from_bytes(mut_flashimg_bytes[COMBO_DIR_LEN_ADDR:COMBO_DIR_LEN_ADDR+4],'little') * 16 + 0x8
+            dir_data_1 = mut_flashimg_bytes[COMBO_DIR_LEN_ADDR:COMBO_DIR_LEN_ADDR+dir_len_1]
+            mut_flashimg_bytes[COMBO_DIR_CHECKSUM_ADDR:COMBO_DIR_CHECKSUM_ADDR+4] = fletcher32(dir_data_1)
 
     # Write to new file
     with open(os.path.join(new_flashimg_dir_path, solutions_file_path[-16:] + "_full.ROM"), 'wb') as f: